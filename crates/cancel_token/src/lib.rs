@@ -1,26 +1,274 @@
-use std::ops::Deref;
-use std::sync::Arc;
+//! A flag shared between threads that tells them to stop, which can be waited on.
+
+#[cfg(loom)]
+use loom::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Condvar, Mutex,
+};
+#[cfg(not(loom))]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Condvar, Mutex,
+};
+use std::time::{Duration, Instant};
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// Clones share the same state, cancelling one cancels all of them. Once cancelled a token
+/// stays cancelled.
+///
+/// Tokens made with [`CancelToken::child`] are cancelled along with their parent, but can
+/// be cancelled on their own without affecting the parent.
 #[derive(Clone)]
 pub struct CancelToken {
-    state: Arc<bool>,
+    state: Arc<State>,
 }
+
+struct State {
+    cancelled: AtomicBool,
+    /// Also the lock the condvar waits with
+    waiting: Mutex<Waiting>,
+    condvar: Condvar,
+}
+
+/// What happens when the token is cancelled
+#[derive(Default)]
+struct Waiting {
+    callbacks: Vec<Callback>,
+    children: Vec<CancelToken>,
+}
+
 impl CancelToken {
     pub fn new() -> Self {
         Self {
-            state: Arc::new(false),
+            state: Arc::new(State {
+                cancelled: AtomicBool::new(false),
+                waiting: Mutex::new(Waiting::default()),
+                condvar: Condvar::new(),
+            }),
+        }
+    }
+
+    /// A token that is cancelled when this one is, but can also be cancelled by itself
+    pub fn child(&self) -> Self {
+        let child = Self::new();
+        let mut waiting = self.state.waiting.lock().unwrap();
+        if self.is_cancelled() {
+            drop(waiting);
+            child.set();
+            return child;
         }
+        // Only the parent has the children nobody uses anymore, they can go along with the
+        // cancelled ones, so making a child for every reconnect doesn't pile them up
+        waiting
+            .children
+            .retain(|child| !child.is_cancelled() && Arc::strong_count(&child.state) > 1);
+        waiting.children.push(child.clone());
+        child
     }
+
+    /// Cancels the token and its children, wakes up everyone waiting on them and runs the
+    /// callbacks on this thread. Does nothing if it was cancelled already
     pub fn set(&self) {
-        // SAFETY: Can't point to invalid address
-        unsafe {
-            let state: *mut bool = Arc::as_ptr(&self.state) as *mut bool;
-            *state = true
+        let waiting = {
+            let mut waiting = self.state.waiting.lock().unwrap();
+            // Set under the lock, so a waiter can't check the flag and then miss the wakeup
+            if self.state.cancelled.swap(true, Ordering::AcqRel) {
+                return;
+            }
+            std::mem::take(&mut *waiting)
+        };
+        self.state.condvar.notify_all();
+        // Outside of the lock, callbacks may use the token too
+        for child in waiting.children {
+            child.set();
+        }
+        for callback in waiting.callbacks {
+            callback();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Blocks until the token is cancelled
+    pub fn wait(&self) {
+        let mut waiting = self.state.waiting.lock().unwrap();
+        while !self.is_cancelled() {
+            waiting = self.state.condvar.wait(waiting).unwrap();
+        }
+    }
+
+    /// Blocks until the token is cancelled or the timeout is over, returns whether it was
+    /// cancelled
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut waiting = self.state.waiting.lock().unwrap();
+        loop {
+            if self.is_cancelled() {
+                return true;
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return false;
+            }
+            waiting = self.state.condvar.wait_timeout(waiting, left).unwrap().0;
+        }
+    }
+
+    /// Runs `callback` once the token is cancelled, on the thread that cancels it. Runs it
+    /// right away if it is cancelled already
+    pub fn on_cancel(&self, callback: impl FnOnce() + Send + 'static) {
+        let mut waiting = self.state.waiting.lock().unwrap();
+        if !self.is_cancelled() {
+            waiting.callbacks.push(Box::new(callback));
+            return;
         }
+        drop(waiting);
+        callback();
     }
 }
-impl Deref for CancelToken {
-    type Target = bool;
-    fn deref(&self) -> &Self::Target {
-        &self.state
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    #[test]
+    fn set_is_seen_by_every_clone() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.set();
+        token.set();
+        assert!(clone.is_cancelled());
+        assert!(!CancelToken::default().wait_timeout(Duration::ZERO));
+    }
+
+    #[test]
+    fn wait_returns_once_cancelled() {
+        let token = CancelToken::new();
+        let waiters: Vec<_> = (0..8)
+            .map(|_| {
+                let token = token.clone();
+                thread::spawn(move || token.wait())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(20));
+        token.set();
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        // Doesn't block anymore
+        token.wait();
+    }
+
+    #[test]
+    fn wait_timeout_times_out() {
+        let token = CancelToken::new();
+        let start = Instant::now();
+        assert!(!token.wait_timeout(Duration::from_millis(30)));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        let waiter = {
+            let token = token.clone();
+            thread::spawn(move || token.wait_timeout(Duration::from_secs(10)))
+        };
+        token.set();
+        assert!(waiter.join().unwrap());
+    }
+
+    #[test]
+    fn callbacks_run_once() {
+        let token = CancelToken::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        for _ in 0..3 {
+            let calls = Arc::clone(&calls);
+            token.on_cancel(move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        // Callbacks may use the token
+        let clone = token.clone();
+        token.on_cancel(move || assert!(clone.is_cancelled()));
+        token.set();
+        token.set();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // Registered after the fact, runs right away
+        let late = Arc::clone(&calls);
+        token.on_cancel(move || {
+            late.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn children_follow_the_parent_only() {
+        let parent = CancelToken::new();
+        let child = parent.child();
+        let grandchild = child.child();
+        let sibling = parent.child();
+        child.set();
+        assert!(grandchild.is_cancelled());
+        assert!(!parent.is_cancelled());
+        assert!(!sibling.is_cancelled());
+
+        let waiter = {
+            let sibling = sibling.clone();
+            thread::spawn(move || sibling.wait())
+        };
+        parent.set();
+        waiter.join().unwrap();
+        assert!(parent.child().is_cancelled());
+    }
+
+    #[test]
+    fn dropped_and_cancelled_children_are_forgotten() {
+        let parent = CancelToken::new();
+        let kept = parent.child();
+        for _ in 0..10 {
+            parent.child().set();
+            drop(parent.child());
+        }
+        let last = parent.child();
+        assert_eq!(parent.state.waiting.lock().unwrap().children.len(), 2);
+        parent.set();
+        assert!(kept.is_cancelled() && last.is_cancelled());
+    }
+
+    #[test]
+    fn stress_concurrent_set_wait_and_callbacks() {
+        for _ in 0..200 {
+            let token = CancelToken::new();
+            let calls = Arc::new(AtomicUsize::new(0));
+            let threads: Vec<_> = (0..4)
+                .map(|i| {
+                    let token = token.clone();
+                    let calls = Arc::clone(&calls);
+                    thread::spawn(move || match i {
+                        0 => token.set(),
+                        1 => token.wait(),
+                        2 => assert!(token.wait_timeout(Duration::from_secs(10))),
+                        _ => token.on_cancel(move || {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                        }),
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert!(token.is_cancelled());
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+        }
     }
 }
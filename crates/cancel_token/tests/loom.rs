@@ -0,0 +1,84 @@
+//! Model checked with `RUSTFLAGS="--cfg loom" cargo test -p cancel_token --test loom --release`
+#![cfg(loom)]
+
+use cancel_token::CancelToken;
+use loom::sync::atomic::{AtomicBool, Ordering};
+use loom::sync::Arc;
+use loom::thread;
+
+#[test]
+fn set_is_visible_to_other_threads() {
+    loom::model(|| {
+        let token = CancelToken::new();
+        let data = Arc::new(AtomicBool::new(false));
+        let setter = {
+            let token = token.clone();
+            let data = Arc::clone(&data);
+            thread::spawn(move || {
+                data.store(true, Ordering::Relaxed);
+                token.set();
+            })
+        };
+        // Everything written before cancelling is seen once it's cancelled
+        if token.is_cancelled() {
+            assert!(data.load(Ordering::Relaxed));
+        }
+        setter.join().unwrap();
+        assert!(token.is_cancelled());
+    });
+}
+
+#[test]
+fn wait_never_misses_the_wakeup() {
+    loom::model(|| {
+        let token = CancelToken::new();
+        let waiter = {
+            let token = token.clone();
+            thread::spawn(move || token.wait())
+        };
+        token.set();
+        waiter.join().unwrap();
+    });
+}
+
+#[test]
+fn callbacks_run_exactly_once() {
+    loom::model(|| {
+        let token = CancelToken::new();
+        let called = Arc::new(AtomicBool::new(false));
+        let registering = {
+            let token = token.clone();
+            let called = Arc::clone(&called);
+            thread::spawn(move || {
+                token.on_cancel(move || assert!(!called.swap(true, Ordering::SeqCst)));
+            })
+        };
+        let setting = {
+            let token = token.clone();
+            thread::spawn(move || token.set())
+        };
+        token.set();
+        registering.join().unwrap();
+        setting.join().unwrap();
+        assert!(called.load(Ordering::SeqCst));
+    });
+}
+
+#[test]
+fn children_are_cancelled_with_the_parent() {
+    loom::model(|| {
+        let parent = CancelToken::new();
+        let making = {
+            let parent = parent.clone();
+            thread::spawn(move || parent.child())
+        };
+        let setting = {
+            let parent = parent.clone();
+            thread::spawn(move || parent.set())
+        };
+        // Made before or after the cancellation, the child ends up cancelled either way
+        let child = making.join().unwrap();
+        setting.join().unwrap();
+        assert!(child.is_cancelled());
+    });
+}
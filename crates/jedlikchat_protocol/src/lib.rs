@@ -0,0 +1,287 @@
+//! The line based JedlikChat wire protocol, shared by the client and the reference server.
+//!
+//! Every frame is one line terminated by `\n`, in both directions. The [`fmt::Display`]
+//! impls write a frame without the terminator, senders add it. Clients older than this crate
+//! wrote frames with no terminator and relied on each `write` arriving as its own read,
+//! which TCP doesn't guarantee.
+
+use std::{error::Error, fmt, str::FromStr};
+
+const BROADCAST_MARKER: &str = " (ALL)";
+
+/// A line sent by the client to the server.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ClientFrame {
+    /// `ID:<nickname>`, the handshake sent right after connecting.
+    Id(String),
+    /// `ALL:<message>`, a message to everyone.
+    All(String),
+    /// `SEND:<id>:<message>`, a message to a single user.
+    Send { to: String, message: String },
+    /// Any line with a tag this client doesn't know about, kept verbatim.
+    Unknown(String),
+}
+
+/// A line sent by the server to the client.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ServerFrame {
+    /// `MSG:<sender>[ (ALL)]:<message>`
+    Msg {
+        sender: String,
+        broadcast: bool,
+        message: String,
+    },
+    /// `USERS:<name>,<name>,...`
+    Users(Vec<String>),
+    /// Any line with a tag this client doesn't know about, kept verbatim.
+    Unknown(String),
+}
+
+/// Nicknames end up in `MSG:` headers and comma separated `USERS:` lists, so they can't
+/// contain the separators of either.
+pub fn is_valid_nickname(name: &str) -> bool {
+    !name.is_empty() && !name.contains([':', ',', '\n', '\r'])
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseError {
+    /// The line has a known tag but is missing one of its `:` separated fields.
+    MissingField {
+        tag: &'static str,
+        field: &'static str,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MissingField { tag, field } => {
+                write!(f, "{tag} frame is missing the {field} field")
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl FromStr for ClientFrame {
+    type Err = ParseError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let Some((tag, rest)) = line.split_once(':') else {
+            return Ok(ClientFrame::Unknown(line.to_owned()));
+        };
+        match tag {
+            "ID" => Ok(ClientFrame::Id(rest.to_owned())),
+            "ALL" => Ok(ClientFrame::All(rest.to_owned())),
+            "SEND" => {
+                let (to, message) = rest.split_once(':').ok_or(ParseError::MissingField {
+                    tag: "SEND",
+                    field: "message",
+                })?;
+                Ok(ClientFrame::Send {
+                    to: to.to_owned(),
+                    message: message.to_owned(),
+                })
+            }
+            _ => Ok(ClientFrame::Unknown(line.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for ClientFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientFrame::Id(name) => write!(f, "ID:{name}"),
+            ClientFrame::All(message) => write!(f, "ALL:{message}"),
+            ClientFrame::Send { to, message } => write!(f, "SEND:{to}:{message}"),
+            ClientFrame::Unknown(line) => f.write_str(line),
+        }
+    }
+}
+
+impl FromStr for ServerFrame {
+    type Err = ParseError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let Some((tag, rest)) = line.split_once(':') else {
+            return Ok(ServerFrame::Unknown(line.to_owned()));
+        };
+        match tag {
+            "MSG" => {
+                let (header, message) = rest.split_once(':').ok_or(ParseError::MissingField {
+                    tag: "MSG",
+                    field: "message",
+                })?;
+                let (sender, broadcast) = match header.strip_suffix(BROADCAST_MARKER) {
+                    Some(sender) => (sender, true),
+                    None => (header, false),
+                };
+                Ok(ServerFrame::Msg {
+                    sender: sender.to_owned(),
+                    broadcast,
+                    message: message.to_owned(),
+                })
+            }
+            "USERS" if rest.is_empty() => Ok(ServerFrame::Users(vec![])),
+            "USERS" => Ok(ServerFrame::Users(
+                rest.split(',').map(|i| i.to_owned()).collect(),
+            )),
+            _ => Ok(ServerFrame::Unknown(line.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for ServerFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerFrame::Msg {
+                sender,
+                broadcast,
+                message,
+            } => {
+                let marker = if *broadcast { BROADCAST_MARKER } else { "" };
+                write!(f, "MSG:{sender}{marker}:{message}")
+            }
+            ServerFrame::Users(users) => write!(f, "USERS:{}", users.join(",")),
+            ServerFrame::Unknown(line) => f.write_str(line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip_server(line: &str) -> ServerFrame {
+        let frame: ServerFrame = line.parse().unwrap();
+        assert_eq!(frame.to_string(), line);
+        frame
+    }
+
+    fn round_trip_client(line: &str) -> ClientFrame {
+        let frame: ClientFrame = line.parse().unwrap();
+        assert_eq!(frame.to_string(), line);
+        frame
+    }
+
+    #[test]
+    fn msg_keeps_colons_in_body() {
+        let frame = round_trip_server("MSG:anna:meet at 10:30: room 2");
+        assert_eq!(
+            frame,
+            ServerFrame::Msg {
+                sender: "anna".into(),
+                broadcast: false,
+                message: "meet at 10:30: room 2".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn msg_broadcast_with_spaces_in_nickname() {
+        let frame = round_trip_server("MSG:Kiss Anna (ALL):hello all");
+        assert_eq!(
+            frame,
+            ServerFrame::Msg {
+                sender: "Kiss Anna".into(),
+                broadcast: true,
+                message: "hello all".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn msg_with_empty_body() {
+        let frame = round_trip_server("MSG:bob:");
+        assert_eq!(
+            frame,
+            ServerFrame::Msg {
+                sender: "bob".into(),
+                broadcast: false,
+                message: "".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn msg_without_body_is_an_error() {
+        assert_eq!(
+            "MSG:bob".parse::<ServerFrame>(),
+            Err(ParseError::MissingField {
+                tag: "MSG",
+                field: "message"
+            })
+        );
+    }
+
+    #[test]
+    fn users_list() {
+        let frame = round_trip_server("USERS:anna,Kiss Bela,c");
+        assert_eq!(
+            frame,
+            ServerFrame::Users(vec!["anna".into(), "Kiss Bela".into(), "c".into()])
+        );
+    }
+
+    #[test]
+    fn empty_users_list() {
+        assert_eq!(round_trip_server("USERS:"), ServerFrame::Users(vec![]));
+    }
+
+    #[test]
+    fn unknown_server_lines_are_kept() {
+        assert_eq!(
+            round_trip_server("PING:1:2"),
+            ServerFrame::Unknown("PING:1:2".into())
+        );
+        assert_eq!(
+            round_trip_server("garbage"),
+            ServerFrame::Unknown("garbage".into())
+        );
+        assert_eq!(round_trip_server(""), ServerFrame::Unknown("".into()));
+    }
+
+    #[test]
+    fn client_frames() {
+        assert_eq!(
+            round_trip_client("ID:Kiss Anna"),
+            ClientFrame::Id("Kiss Anna".into())
+        );
+        assert_eq!(
+            round_trip_client("ALL:a: b :c"),
+            ClientFrame::All("a: b :c".into())
+        );
+        assert_eq!(
+            round_trip_client("SEND:Kiss Anna:at 10:30"),
+            ClientFrame::Send {
+                to: "Kiss Anna".into(),
+                message: "at 10:30".into(),
+            }
+        );
+        assert_eq!(
+            round_trip_client("NOPE"),
+            ClientFrame::Unknown("NOPE".into())
+        );
+    }
+
+    #[test]
+    fn nicknames() {
+        assert!(is_valid_nickname("Kiss Anna"));
+        assert!(is_valid_nickname("Árvíztűrő"));
+        assert!(!is_valid_nickname(""));
+        assert!(!is_valid_nickname("a:b"));
+        assert!(!is_valid_nickname("a,b"));
+    }
+
+    #[test]
+    fn send_without_message_is_an_error() {
+        assert_eq!(
+            "SEND:anna".parse::<ClientFrame>(),
+            Err(ParseError::MissingField {
+                tag: "SEND",
+                field: "message"
+            })
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Reference implementation of a JedlikChat server.
+//!
+//! Every client gets its own thread, the first line it sends has to be the `ID:` handshake.
+//! Nicknames are unique, a second client trying to take a used name is disconnected.
+//! Broadcasts aren't echoed back to their sender, clients show their own messages locally.
+
+use jedlikchat_protocol::{is_valid_nickname, ClientFrame, ServerFrame};
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// The writing half of a client, locked so concurrent frames don't interleave
+type Client = Arc<Mutex<TcpStream>>;
+type Registry = Arc<Mutex<BTreeMap<String, Client>>>;
+
+pub struct Server {
+    listener: TcpListener,
+    clients: Registry,
+}
+
+impl Server {
+    pub fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            clients: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts clients forever
+    pub fn run(self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Couldn't accept client: {e}");
+                    continue;
+                }
+            };
+            let clients = Arc::clone(&self.clients);
+            thread::spawn(move || {
+                if let Err(e) = handle_client(stream, &clients) {
+                    eprintln!("Client error: {e}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle_client(stream: TcpStream, clients: &Registry) -> io::Result<()> {
+    let mut lines = BufReader::new(stream.try_clone()?).lines();
+    let name = match lines.next().transpose()?.map(|line| line.parse()) {
+        Some(Ok(ClientFrame::Id(name))) if is_valid_nickname(&name) => name,
+        _ => return Ok(()),
+    };
+    {
+        let mut clients = clients.lock().unwrap();
+        if clients.contains_key(&name) {
+            return Ok(());
+        }
+        clients.insert(name.clone(), Arc::new(Mutex::new(stream)));
+    }
+    broadcast_users(clients);
+    eprintln!("{name} joined");
+
+    for line in lines {
+        let Ok(line) = line else {
+            break;
+        };
+        match line.parse() {
+            Ok(ClientFrame::All(message)) => {
+                let frame = ServerFrame::Msg {
+                    sender: name.clone(),
+                    broadcast: true,
+                    message,
+                };
+                let others: Vec<_> = clients
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|(client, _)| **client != name)
+                    .map(|(_, client)| Arc::clone(client))
+                    .collect();
+                send(others, &frame);
+            }
+            Ok(ClientFrame::Send { to, message }) => {
+                let frame = ServerFrame::Msg {
+                    sender: name.clone(),
+                    broadcast: false,
+                    message,
+                };
+                let recipient = clients.lock().unwrap().get(&to).cloned();
+                send(recipient, &frame);
+            }
+            _ => {}
+        }
+    }
+
+    clients.lock().unwrap().remove(&name);
+    broadcast_users(clients);
+    eprintln!("{name} left");
+    Ok(())
+}
+
+fn broadcast_users(clients: &Registry) {
+    let (frame, everyone) = {
+        let clients = clients.lock().unwrap();
+        let frame = ServerFrame::Users(clients.keys().cloned().collect());
+        (frame, clients.values().cloned().collect::<Vec<_>>())
+    };
+    send(everyone, &frame);
+}
+
+/// Writes outside of the registry lock, so a stalled client only holds up the thread writing
+/// to it instead of every connection
+fn send(clients: impl IntoIterator<Item = Client>, frame: &ServerFrame) {
+    for client in clients {
+        let _ = write_frame(&mut *client.lock().unwrap(), frame);
+    }
+}
+
+fn write_frame(mut stream: impl Write, frame: &ServerFrame) -> io::Result<()> {
+    writeln!(stream, "{frame}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    struct Client {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Client {
+        fn connect(address: SocketAddr, name: &str) -> Self {
+            let stream = TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            let mut client = Self { stream, reader };
+            client.send(&format!("ID:{name}"));
+            client
+        }
+
+        fn send(&mut self, line: &str) {
+            writeln!(self.stream, "{line}").unwrap();
+        }
+
+        fn expect(&mut self, line: &str) {
+            let mut received = String::new();
+            self.reader.read_line(&mut received).unwrap();
+            assert_eq!(received.trim_end_matches('\n'), line);
+        }
+    }
+
+    fn start() -> SocketAddr {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        address
+    }
+
+    #[test]
+    fn user_list_follows_joins_and_leaves() {
+        let address = start();
+        let mut anna = Client::connect(address, "Kiss Anna");
+        anna.expect("USERS:Kiss Anna");
+        let mut bela = Client::connect(address, "bela");
+        anna.expect("USERS:Kiss Anna,bela");
+        bela.expect("USERS:Kiss Anna,bela");
+        drop(bela);
+        anna.expect("USERS:Kiss Anna");
+    }
+
+    #[test]
+    fn broadcast_and_direct_messages() {
+        let address = start();
+        let mut anna = Client::connect(address, "anna");
+        anna.expect("USERS:anna");
+        let mut bela = Client::connect(address, "bela");
+        anna.expect("USERS:anna,bela");
+        bela.expect("USERS:anna,bela");
+        let mut cili = Client::connect(address, "cili");
+        anna.expect("USERS:anna,bela,cili");
+        bela.expect("USERS:anna,bela,cili");
+        cili.expect("USERS:anna,bela,cili");
+
+        anna.send("ALL:hi: everyone");
+        bela.expect("MSG:anna (ALL):hi: everyone");
+        cili.expect("MSG:anna (ALL):hi: everyone");
+
+        bela.send("SEND:anna:only for you");
+        anna.expect("MSG:bela:only for you");
+
+        // cili only ever got the broadcast, the next line is the one after it
+        bela.send("SEND:cili:ping");
+        cili.expect("MSG:bela:ping");
+    }
+
+    #[test]
+    fn stalled_client_doesnt_block_the_others() {
+        let address = start();
+        let mut stalled = Client::connect(address, "stalled");
+        stalled.expect("USERS:stalled");
+        let mut anna = Client::connect(address, "anna");
+        anna.expect("USERS:anna,stalled");
+        let mut bela = Client::connect(address, "bela");
+        anna.expect("USERS:anna,bela,stalled");
+        bela.expect("USERS:anna,bela,stalled");
+
+        // stalled never reads, so the thread of anna ends up stuck writing to it
+        let mut flood = anna.stream.try_clone().unwrap();
+        thread::spawn(move || {
+            let big = "x".repeat(64 * 1024);
+            for _ in 0..512 {
+                if writeln!(flood, "SEND:stalled:{big}").is_err() {
+                    break;
+                }
+            }
+        });
+        thread::sleep(Duration::from_millis(500));
+        bela.send("SEND:anna:still here?");
+        anna.expect("MSG:bela:still here?");
+    }
+
+    #[test]
+    fn taken_nickname_is_rejected() {
+        let address = start();
+        let mut anna = Client::connect(address, "anna");
+        anna.expect("USERS:anna");
+        let mut impostor = Client::connect(address, "anna");
+        let mut line = String::new();
+        assert_eq!(impostor.reader.read_line(&mut line).unwrap(), 0);
+    }
+}
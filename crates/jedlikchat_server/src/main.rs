@@ -0,0 +1,12 @@
+use jedlikchat_server::Server;
+use std::{env, error::Error};
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:5000";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let address = env::args().nth(1).unwrap_or(DEFAULT_ADDRESS.to_owned());
+    let server = Server::bind(&address)?;
+    eprintln!("Listening on {}", server.local_addr()?);
+    server.run()?;
+    Ok(())
+}
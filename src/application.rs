@@ -1,23 +1,31 @@
-use crate::networking::{self, Session};
+use crate::networking::{self, MessageId, Recipient, ReconnectPolicy, Session};
+use crate::{crash, utils};
 use cancel_token::CancelToken;
-use crossterm::event::{self};
-use ratatui::{DefaultTerminal, Frame};
+use crossterm::event::{
+    self, DisableMouseCapture, EnableMouseCapture, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::terminal::{enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen};
+use crossterm::execute;
+use ratatui::backend::CrosstermBackend;
+use ratatui::{DefaultTerminal, Frame, Terminal};
 use std::error::Error;
+use std::io::stdout;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
-use std::thread::{self, JoinHandle};
+use std::thread::JoinHandle;
 use std::time::Duration;
 type Res<T> = Result<T, Box<dyn Error>>;
+type Connected = (Session, Receiver<networking::Event>, CancelToken);
+/// How long shutting down waits for each thread
+const JOIN_TIMEOUT: Duration = Duration::from_secs(1);
 pub trait Application {
     fn handle_event(&mut self, event_loop: &mut ActiveEventLoop, event: GeneralEvent);
 
     fn init(&mut self, event_loop: &mut ActiveEventLoop);
 
     fn render(&mut self, frame: &mut Frame);
-
-    fn redraw(&mut self, mut terminal: DefaultTerminal) -> Res<()> {
-        terminal.draw(|frame| self.render(frame))?;
-        Ok(())
-    }
 }
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum GeneralEvent {
@@ -25,6 +33,16 @@ pub enum GeneralEvent {
     Input(crossterm::event::Event),
     RedrawRequested,
     Exit,
+    /// A session from [`ActiveEventLoop::start_network_session`] connected to the address or
+    /// failed to. The event loop has already put it in place when the application gets this
+    SessionStarted(Result<SocketAddr, String>),
+    /// A thread other than the main one panicked, the event loop shuts down
+    Panicked {
+        thread: String,
+        message: String,
+        /// The crash report, if it could be written
+        report: Option<PathBuf>,
+    },
 }
 
 pub struct ActiveEventLoop {
@@ -32,14 +50,21 @@ pub struct ActiveEventLoop {
     event_sender: Sender<GeneralEvent>,
     network_handle: Option<JoinHandle<()>>,
     network_session: Option<Session>,
+    /// The thread connecting the next session, it sends [`GeneralEvent::SessionStarted`] once done
+    connect_handle: Option<JoinHandle<Option<Connected>>>,
     input_handle: Option<JoinHandle<()>>,
     event_receiver: Option<Receiver<GeneralEvent>>,
 }
 
 impl ActiveEventLoop {
+    /// Stops the session and waits for the thread forwarding its events, then for its reader
     pub fn stop_network_session(&mut self) {
         if let Some(session) = self.network_session.take() {
             session.stop();
+            if let Some(network_handle) = self.network_handle.take() {
+                utils::join_timeout(network_handle, JOIN_TIMEOUT, "network forwarding");
+            }
+            session.shutdown(JOIN_TIMEOUT);
         }
     }
     fn set_exit_flag(&self) {
@@ -57,6 +82,7 @@ impl ActiveEventLoop {
             event_sender,
             network_handle: None,
             network_session: None,
+            connect_handle: None,
             input_handle: None,
             event_receiver: Some(event_receiver),
         };
@@ -64,50 +90,126 @@ impl ActiveEventLoop {
         active_loop
     }
 
-    fn start_application(mut self, application: &mut impl Application) {
+    fn start_application(mut self, application: &mut impl Application) -> Res<()> {
+        crash::attach(self.event_sender.clone());
         application.init(&mut self);
         let receiver = self.event_receiver.take().unwrap();
-        let mut terminal = ratatui::init();
+        let mut terminal = init_terminal();
 
+        let mut result = Ok(());
         for event in receiver.iter() {
+            if event != GeneralEvent::RedrawRequested {
+                crash::record(&event);
+            }
             match event.clone() {
-                GeneralEvent::Exit => {
-                    self.set_exit_flag();
-                    self.stop_network_session();
+                GeneralEvent::Exit => break,
+                GeneralEvent::Panicked { thread, message, report } => {
+                    let report = report
+                        .map(|path| format!(", the crash report is in {}", path.display()))
+                        .unwrap_or_default();
+                    result = Err(format!("The {thread} thread panicked: {message}{report}").into());
                     break;
                 }
                 GeneralEvent::RedrawRequested => {
                     let _ = terminal.draw(|frame| application.render(frame));
                 }
+                GeneralEvent::SessionStarted(result) => {
+                    self.finish_network_session();
+                    application.handle_event(&mut self, GeneralEvent::SessionStarted(result));
+                }
                 event => {
-                    let _ = application.handle_event(&mut self, event);
+                    application.handle_event(&mut self, event);
                 }
             };
         }
-        self.input_handle.take().unwrap().join().unwrap_or_else(|_| eprintln!("Couldn't join on handle"));
-        if let Some(network_handle) = self.network_handle.take() {
-            network_handle.join().unwrap_or_else(|_| eprintln!("Couldn't join on handle"));
+        self.shut_down();
+        result
+    }
+    /// Stops every thread and waits for them in order: input, network forwarding, network
+    /// reader. The terminal is restored after this by `run_app`
+    fn shut_down(&mut self) {
+        log::info!("shutting down");
+        // Panics from here on are only logged, nothing would handle the event
+        crash::detach();
+        // Also cancels the session's child token, which shuts down its connection
+        self.set_exit_flag();
+        if let Some(input_handle) = self.input_handle.take() {
+            utils::join_timeout(input_handle, JOIN_TIMEOUT, "input");
+        }
+        // A connect can't be interrupted, its session is stopped by the token once it's done
+        if let Some(connect_handle) = self.connect_handle.take() {
+            utils::join_timeout(connect_handle, JOIN_TIMEOUT, "connect");
         }
+        self.stop_network_session();
     }
     pub fn exit(&self) {
         self.set_exit_flag();
         self.event_sender.send(GeneralEvent::Exit).unwrap_or_else(|_| {
             eprintln!("Couldn't request redraw, there is something wrong with the event loop");
             self.cancel_token.set()});
-        ratatui::restore();
     }
-    pub fn start_network_session(&mut self, name: &str, socket: &str) -> Res<()> {
-        let (session, network_receiver) = Session::new(name, socket, self.cancel_token.clone())?;
-        self.network_handle = Some(self.wrap_network(network_receiver, self.event_sender.clone()));
-        self.network_session = Some(session);
+    /// Starts connecting a session on another thread, `resolve` finds the address there too,
+    /// so a slow lookup or connect doesn't block the event loop. Once connected it replaces the
+    /// current session and the application gets [`GeneralEvent::SessionStarted`].
+    ///
+    /// Sessions get a child of the app's token, so stopping one leaves the rest of the app
+    /// running but exiting stops the session
+    pub fn start_network_session(
+        &mut self,
+        name: &str,
+        policy: ReconnectPolicy,
+        resolve: impl FnOnce() -> Result<SocketAddr, String> + Send + 'static,
+    ) -> Res<()> {
+        if self.connect_handle.is_some() {
+            return Err("already connecting".into());
+        }
+        let session_token = self.cancel_token.child();
+        let event_sender = self.event_sender.clone();
+        let name = name.to_owned();
+        self.connect_handle = Some(utils::spawn("connect", move || {
+            let connected = resolve().and_then(|address| {
+                Session::with_policy(&name, &address.to_string(), session_token.clone(), policy)
+                    .map(|(session, receiver)| (address, session, receiver))
+                    .map_err(|e| format!("Couldn't connect to {address}: {e}"))
+            });
+            match connected {
+                Ok((address, session, receiver)) => {
+                    let _ = event_sender.send(GeneralEvent::SessionStarted(Ok(address)));
+                    Some((session, receiver, session_token))
+                }
+                Err(e) => {
+                    let _ = event_sender.send(GeneralEvent::SessionStarted(Err(e)));
+                    None
+                }
+            }
+        }));
         Ok(())
     }
+    /// Puts the session the connect thread made in place of the current one
+    fn finish_network_session(&mut self) {
+        let Some(connect_handle) = self.connect_handle.take() else {
+            return;
+        };
+        let Ok(Some((session, network_receiver, session_token))) = connect_handle.join() else {
+            return;
+        };
+        self.stop_network_session();
+        let events = self.event_sender.clone();
+        self.network_handle = Some(self.wrap_network(network_receiver, events, session_token));
+        self.network_session = Some(session);
+    }
+    pub fn send_message(&self, recipient: Recipient, message: &str) -> Res<MessageId> {
+        match &self.network_session {
+            Some(session) => session.send(recipient, message),
+            None => Err("not connected".into()),
+        }
+    }
     fn start_input_listener(&mut self) {
         let event_sender = self.event_sender.clone();
         let exit = self.cancel_token.clone();
 
-        self.input_handle = Some(thread::spawn(move || loop {
-            if *exit {
+        self.input_handle = Some(utils::spawn("input", move || loop {
+            if exit.is_cancelled() {
                 break;
             }
             let is_event = match event::poll(Duration::from_millis(50)) {
@@ -116,7 +218,7 @@ impl ActiveEventLoop {
             };
             if is_event {
                 let event = event::read().unwrap();
-                let _ = event_sender.send(GeneralEvent::Input(event)).unwrap();
+                event_sender.send(GeneralEvent::Input(event)).unwrap();
             }
         }))
     }
@@ -125,10 +227,10 @@ impl ActiveEventLoop {
         &self,
         network_receiver: Receiver<networking::Event>,
         general_sender: Sender<GeneralEvent>,
+        session_token: CancelToken,
     ) -> JoinHandle<()> {
-        let exit = self.cancel_token.clone();
-        thread::spawn(move || loop {
-            if *exit {
+        utils::spawn("network forwarding", move || loop {
+            if session_token.is_cancelled() {
                 break;
             }
             match network_receiver.recv_timeout(Duration::from_millis(50)) {
@@ -144,16 +246,38 @@ impl ActiveEventLoop {
     }
 }
 
+/// Like `ratatui::init`, but without its panic hook, [`crash`] restores the terminal
+fn init_terminal() -> DefaultTerminal {
+    enable_raw_mode().expect("Couldn't enable raw mode");
+    let _ = execute!(stdout(), EnterAlternateScreen);
+    let terminal =
+        Terminal::new(CrosstermBackend::new(stdout())).expect("Couldn't set up the terminal");
+    let _ = execute!(stdout(), EnableMouseCapture);
+    // Needed to tell Shift+Enter apart from Enter, terminals without it still have Alt+Enter
+    if supports_keyboard_enhancement().unwrap_or(false) {
+        let _ = execute!(
+            stdout(),
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+        );
+    }
+    terminal
+}
+
+pub fn restore_terminal() {
+    let _ = execute!(stdout(), PopKeyboardEnhancementFlags, DisableMouseCapture);
+    ratatui::restore();
+}
+
 pub struct EventLoop {}
 impl EventLoop {
     pub fn new() -> Self {
         EventLoop {}
     }
     pub fn run_app<T: Application>(&mut self, application: &mut T) -> Res<()> {
-        ActiveEventLoop::new().start_application(application);
+        let result = ActiveEventLoop::new().start_application(application);
 
-        ratatui::restore();
+        restore_terminal();
 
-        Ok(())
+        result
     }
 }
@@ -0,0 +1,300 @@
+use crate::networking::{unix_seconds, MessageInformation, Recipient};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How long logged messages are kept
+#[derive(Debug, PartialEq, Clone)]
+pub struct Retention {
+    /// Nothing is written or restored if disabled
+    pub enabled: bool,
+    /// Older messages are dropped, `None` keeps them forever
+    pub max_age: Option<Duration>,
+    /// Only this many of the newest messages are kept per conversation, `None` keeps all
+    pub max_messages: Option<usize>,
+    /// Messages put back in each conversation when connecting
+    pub restore: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_age: Some(Duration::from_secs(90 * 24 * 60 * 60)),
+            max_messages: None,
+            restore: 100,
+        }
+    }
+}
+
+/// The logs of one nickname on one server, one file per conversation with a JSON object per
+/// message on each line.
+///
+/// Lives in `<data dir>/jedlikchat/logs/<server>/<nickname>/`, where the data dir is
+/// `$XDG_DATA_HOME` or `~/.local/share`.
+#[derive(Debug, PartialEq)]
+pub struct ChatLog {
+    dir: PathBuf,
+}
+
+impl ChatLog {
+    /// `<data dir>/jedlikchat/logs`, `None` if there is no home directory
+    pub fn default_root() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("jedlikchat").join("logs"))
+    }
+
+    pub fn open(root: &Path, server: &str, nickname: &str) -> io::Result<Self> {
+        let dir = root.join(file_name(server)).join(file_name(nickname));
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, conversation: &Recipient) -> PathBuf {
+        let name = match conversation {
+            Recipient::Id(name) => format!("dm_{}", file_name(name)),
+            Recipient::All | Recipient::This => "all".to_owned(),
+        };
+        self.dir.join(name + ".jsonl")
+    }
+
+    pub fn append(&self, conversation: &Recipient, message: &MessageInformation) -> io::Result<()> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(conversation))?
+            .write_all(line.as_bytes())
+    }
+
+    /// Every message of a conversation, oldest first. Lines that don't parse are skipped
+    pub fn read(&self, conversation: &Recipient) -> io::Result<Vec<MessageInformation>> {
+        let file = match File::open(self.path(conversation)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut messages = vec![];
+        for line in BufReader::new(file).lines() {
+            if let Ok(message) = serde_json::from_str(&line?) {
+                messages.push(message);
+            }
+        }
+        Ok(messages)
+    }
+
+    /// The conversations that have a log, everyone first
+    pub fn conversations(&self) -> io::Result<Vec<Recipient>> {
+        let mut names = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if let Some(name) = name.strip_suffix(".jsonl") {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        let mut conversations: Vec<_> = names
+            .iter()
+            .filter_map(|name| name.strip_prefix("dm_"))
+            .map(|name| Recipient::Id(decode_file_name(name)))
+            .collect();
+        if names.iter().any(|name| name == "all") {
+            conversations.insert(0, Recipient::All);
+        }
+        Ok(conversations)
+    }
+
+    /// Drops the messages the retention policy doesn't keep anymore
+    pub fn apply_retention(&self, retention: &Retention, now: SystemTime) -> io::Result<()> {
+        let oldest = retention
+            .max_age
+            .and_then(|age| now.checked_sub(age))
+            .map(unix_seconds);
+        for conversation in self.conversations()? {
+            let messages = self.read(&conversation)?;
+            let mut kept: Vec<_> = messages
+                .iter()
+                .filter(|message| oldest.is_none_or(|oldest| message.time >= oldest))
+                .collect();
+            if let Some(max) = retention.max_messages {
+                kept.drain(..kept.len().saturating_sub(max));
+            }
+            if kept.len() == messages.len() {
+                continue;
+            }
+            let mut contents = String::new();
+            for message in kept {
+                contents += &serde_json::to_string(message)?;
+                contents.push('\n');
+            }
+            // Written next to the log and renamed, so a crash can't leave half a log behind
+            let path = self.path(&conversation);
+            let temporary = path.with_extension("jsonl.tmp");
+            fs::write(&temporary, contents)?;
+            fs::rename(temporary, path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Percent encodes everything but ASCII letters, digits, `.`, `-` and `_`, so any name is a
+/// valid file name that can't escape the log directory
+fn file_name(name: &str) -> String {
+    let mut encoded = String::new();
+    for byte in name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => encoded.push(byte as char),
+            b'.' if !encoded.is_empty() => encoded.push('.'),
+            byte => encoded += &format!("%{byte:02X}"),
+        }
+    }
+    encoded
+}
+
+fn decode_file_name(name: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = name.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn message(time: u64, sender: &str, recipient: Recipient, message: &str) -> MessageInformation {
+        MessageInformation {
+            sender: sender.into(),
+            recipient,
+            message: message.into(),
+            time,
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn append_and_read_back() {
+        let root = tempfile::tempdir().unwrap();
+        let log = ChatLog::open(root.path(), "127.0.0.1:5000", "Kiss Anna").unwrap();
+        let dm = Recipient::Id("../bela".into());
+        for i in 0..5 {
+            log.append(
+                &Recipient::All,
+                &message(i, "cili", Recipient::All, &format!("m{i}")),
+            )
+            .unwrap();
+        }
+        let multi_line = message(9, "../bela", Recipient::This, "one\ntwo");
+        log.append(&dm, &multi_line).unwrap();
+
+        let all = log.read(&Recipient::All).unwrap();
+        assert_eq!(all[3].message, "m3");
+        assert_eq!(all.len(), 5);
+        assert_eq!(log.read(&dm).unwrap(), [multi_line]);
+        assert_eq!(log.conversations().unwrap(), [Recipient::All, dm]);
+        // Logs written before the message type had a timestamp
+        fs::write(
+            log.path(&Recipient::Id("dora".into())),
+            "{\"time\":7,\"sender\":\"dora\",\"recipient\":\"this\",\"message\":\"hi\"}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            log.read(&Recipient::Id("dora".into())).unwrap(),
+            [message(7, "dora", Recipient::This, "hi")]
+        );
+        assert!(log
+            .read(&Recipient::Id("nobody".into()))
+            .unwrap()
+            .is_empty());
+
+        // Everything stays inside the directory of the server and the nickname
+        let dir = root.path().join("127.0.0.1%3A5000").join("Kiss%20Anna");
+        assert!(dir.join("dm_%2E.%2Fbela.jsonl").exists());
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn logs_are_separate_per_server_and_nickname() {
+        let root = tempfile::tempdir().unwrap();
+        let first = ChatLog::open(root.path(), "host:1", "anna").unwrap();
+        let other_server = ChatLog::open(root.path(), "host:2", "anna").unwrap();
+        let other_nickname = ChatLog::open(root.path(), "host:1", "bela").unwrap();
+        first
+            .append(&Recipient::All, &message(1, "anna", Recipient::All, "hi"))
+            .unwrap();
+        assert!(other_server.conversations().unwrap().is_empty());
+        assert!(other_nickname.conversations().unwrap().is_empty());
+    }
+
+    #[test]
+    fn retention_drops_old_and_excess_messages() {
+        let root = tempfile::tempdir().unwrap();
+        let log = ChatLog::open(root.path(), "host:1", "anna").unwrap();
+        for i in 0..10 {
+            log.append(
+                &Recipient::All,
+                &message(i * 100, "bela", Recipient::All, "hi"),
+            )
+            .unwrap();
+        }
+        let mut retention = Retention {
+            max_age: Some(Duration::from_secs(500)),
+            ..Default::default()
+        };
+        log.apply_retention(&retention, at(1000)).unwrap();
+        let times: Vec<_> = log
+            .read(&Recipient::All)
+            .unwrap()
+            .iter()
+            .map(|r| r.time)
+            .collect();
+        assert_eq!(times, [500, 600, 700, 800, 900]);
+
+        retention.max_age = None;
+        retention.max_messages = Some(2);
+        log.apply_retention(&retention, at(1000)).unwrap();
+        let times: Vec<_> = log
+            .read(&Recipient::All)
+            .unwrap()
+            .iter()
+            .map(|r| r.time)
+            .collect();
+        assert_eq!(times, [800, 900]);
+    }
+
+    #[test]
+    fn file_names_round_trip() {
+        for name in [
+            "Kiss Anna",
+            "..",
+            ".hidden",
+            "a/b\\c",
+            "árvíz 😀",
+            "host:5000",
+        ] {
+            let encoded = file_name(name);
+            assert!(encoded
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "%._-".contains(c)));
+            assert!(!encoded.starts_with('.'));
+            assert_eq!(decode_file_name(&encoded), name);
+        }
+    }
+}
@@ -0,0 +1,595 @@
+use crate::chat_log::ChatLog;
+use crate::config::{Config, ServerProfile};
+use crate::export::{self, Format};
+use crate::networking::{
+    protocol, DeliveryStatus, Event, MessageId, MessageInformation, Recipient, ReconnectPolicy,
+    Session,
+};
+use crate::utils;
+use crate::validate_connect_form;
+use cancel_token::CancelToken;
+use clap::{Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+use serde::Serialize;
+use std::error::Error;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Terminal client for JedlikChat, opens the TUI without a subcommand
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Nickname to use, instead of the one in the config
+    #[arg(long, global = true)]
+    pub nick: Option<String>,
+    /// Server to connect to, like localhost:5000, skips the connect form
+    #[arg(
+        long,
+        global = true,
+        value_name = "HOST:PORT",
+        conflicts_with = "profile"
+    )]
+    pub server: Option<String>,
+    /// Saved server from the config to connect to, skips the connect form
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+    /// Config file to use instead of $JEDLIKCHAT_CONFIG or the default one
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// off, error, warn, info, debug or trace. The TUI logs to a file, the subcommands to
+    /// stderr
+    #[arg(long, global = true, value_name = "LEVEL", default_value = "warn")]
+    pub log_level: LevelFilter,
+    /// How many times to try reconnecting after losing the connection, 0 never gives up
+    #[arg(long, global = true, value_name = "N")]
+    pub reconnect_attempts: Option<u32>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Sends one message and exits
+    Send {
+        /// A nickname, or all for everyone
+        #[arg(long)]
+        to: String,
+        #[arg(required = true, num_args = 1..)]
+        text: Vec<String>,
+    },
+    /// Prints incoming messages until the connection is lost for good
+    Listen,
+    /// Sends every line of stdin and prints every event to stdout, for scripts and bots.
+    ///
+    /// Lines starting with `@nickname ` are sent to that user only, everything else goes to
+    /// everyone. Exits at the end of the input or when the connection is lost for good.
+    Pipe {
+        #[arg(long, value_enum, default_value_t = Output::Text)]
+        format: Output,
+    },
+    /// Exports the chat log of the server and nickname
+    Export {
+        /// markdown, html, jsonl or text
+        format: Format,
+        /// The file to write, - for stdout
+        path: PathBuf,
+        /// A nickname, or everyone, instead of every conversation
+        #[arg(long)]
+        conversation: Option<String>,
+    },
+}
+
+impl Cli {
+    /// Whether the TUI should connect right away instead of showing the connect form
+    pub fn connects(&self) -> bool {
+        self.server.is_some() || self.profile.is_some()
+    }
+
+    /// Puts the server and nickname options in the config, they win over what's in the file
+    pub fn apply(&self, config: &mut Config) -> Result<(), String> {
+        if let Some(name) = &self.profile {
+            if config.server(name).is_none() {
+                return Err(format!("There is no server named {name} in the config"));
+            }
+            config.default_server = Some(name.clone());
+        }
+        if let Some(server) = &self.server {
+            let (host, port) = server
+                .rsplit_once(':')
+                .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+                .ok_or_else(|| format!("--server has to look like host:port, not {server}"))?;
+            config.servers.retain(|profile| profile.name != *server);
+            config.servers.push(ServerProfile {
+                name: server.clone(),
+                host: host.to_owned(),
+                port,
+                nickname: None,
+            });
+            config.default_server = Some(server.clone());
+        }
+        if let Some(nick) = &self.nick {
+            if !protocol::is_valid_nickname(nick) {
+                return Err("--nick has to be non-empty without ':' or ','".to_owned());
+            }
+            config.nickname = Some(nick.clone());
+            let default = config.default_server.clone();
+            if let Some(profile) = config
+                .servers
+                .iter_mut()
+                .find(|profile| Some(&profile.name) == default.as_ref())
+            {
+                profile.nickname = None;
+            }
+        }
+        if let Some(attempts) = self.reconnect_attempts {
+            config.reconnect.enabled = true;
+            config.reconnect.max_attempts = attempts;
+        }
+        Ok(())
+    }
+}
+
+pub fn run(command: Command, config: &Config) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Send { to, text } => send(config, &to, &text.join(" ")),
+        Command::Listen => listen(config),
+        Command::Pipe { format } => pipe(config, format),
+        Command::Export {
+            format,
+            path,
+            conversation,
+        } => export(config, format, &path, conversation.as_deref()),
+    }
+}
+
+/// The nickname and the resolved address of the default server
+fn target(config: &Config) -> Result<(String, SocketAddr), String> {
+    let profile = config
+        .default_profile()
+        .ok_or("No server given, use --server, --profile or default_server in the config")?;
+    let nickname = config
+        .default_nickname()
+        .ok_or("No nickname given, use --nick or nickname in the config")?;
+    validate_connect_form(nickname, &profile.host, &profile.port.to_string())
+}
+
+fn send(config: &Config, to: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    let (nickname, address) = target(config)?;
+    let recipient = match to {
+        "all" => Recipient::All,
+        name => Recipient::Id(name.to_owned()),
+    };
+    // One message isn't worth waiting for a reconnect, a dropped connection fails the send
+    let (session, _events) = Session::with_policy(
+        &nickname,
+        &address.to_string(),
+        CancelToken::new(),
+        ReconnectPolicy::disabled(),
+    )?;
+    // Returns once the frame is written, or with why it couldn't be
+    session.send(recipient, text)?;
+    session.stop();
+    Ok(())
+}
+
+fn listen(config: &Config) -> Result<(), Box<dyn Error>> {
+    let (nickname, address) = target(config)?;
+    let (session, events) = Session::with_policy(
+        &nickname,
+        &address.to_string(),
+        CancelToken::new(),
+        config.reconnect.policy(),
+    )?;
+    while let Some(event) = next_event(&session, &events, || false) {
+        match event {
+            Event::MessageReceived(message) => println!("{}", message_line(&message)),
+            // Everything else is about the connection, kept apart from the messages
+            Event::Connected => eprintln!("connected to {address} as {nickname}"),
+            Event::Disconnected { reason } => eprintln!("disconnected: {reason}"),
+            Event::Reconnecting { attempt, delay } => eprintln!(
+                "reconnecting (attempt {attempt}, in {:.1}s)",
+                delay.as_secs_f32()
+            ),
+            Event::UsersList(users) => eprintln!("{} online: {}", users.len(), users.join(", ")),
+            Event::MessageSent { .. } | Event::Delivery { .. } | Event::Quit => {}
+        }
+    }
+    Ok(())
+}
+
+/// How often waiting for events checks whether the session or the input is over
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The next event, `None` once the session can't send any more or `done` says so
+fn next_event(
+    session: &Session,
+    events: &Receiver<Event>,
+    done: impl Fn() -> bool,
+) -> Option<Event> {
+    loop {
+        match events.recv_timeout(POLL_INTERVAL) {
+            Ok(event) => return Some(event),
+            Err(RecvTimeoutError::Timeout) if session.is_running() && !done() => {}
+            Err(_) => return None,
+        }
+    }
+}
+
+/// What `pipe` prints
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Output {
+    /// Messages like `[12:34] bela: hi`, everything else starting with `* `
+    Text,
+    /// One JSON object per event, the kind of event is in the `event` field
+    Jsonl,
+}
+
+fn pipe(config: &Config, format: Output) -> Result<(), Box<dyn Error>> {
+    let (nickname, address) = target(config)?;
+    let (session, events) = Session::with_policy(
+        &nickname,
+        &address.to_string(),
+        CancelToken::new(),
+        config.reconnect.policy(),
+    )?;
+    let session = Arc::new(session);
+    let input = {
+        let session = Arc::clone(&session);
+        utils::spawn("stdin", move || send_lines(&session))
+    };
+    let mut out = io::stdout().lock();
+    // Whatever is queued when the input ends still gets printed, like the delivery of the last
+    // line
+    while let Some(event) = next_event(&session, &events, || input.is_finished()) {
+        match format {
+            Output::Text => write_text(&mut out, &event, &nickname, &address)?,
+            Output::Jsonl => write_json(&mut out, &event, &nickname)?,
+        }
+    }
+    session.stop();
+    // Still reading if the connection was lost, exiting takes the thread down with it
+    if input.is_finished() {
+        input.join().expect("The input thread panicked")?;
+    }
+    Ok(())
+}
+
+/// Sends stdin line by line until it ends. Failed sends are reported as events, so they don't
+/// stop the rest
+fn send_lines(session: &Session) -> io::Result<()> {
+    loop {
+        let line = utils::read()?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            continue;
+        }
+        let (recipient, text) = parse_line(line);
+        let _ = session.send(recipient, text);
+    }
+}
+
+/// `@bela hi` is a direct message to bela, anything else is for everyone
+fn parse_line(line: &str) -> (Recipient, &str) {
+    let direct = line
+        .strip_prefix('@')
+        .and_then(|line| line.split_once(' '))
+        .filter(|(name, text)| protocol::is_valid_nickname(name) && !text.is_empty());
+    match direct {
+        Some((name, text)) => (Recipient::Id(name.to_owned()), text),
+        None => (Recipient::All, line),
+    }
+}
+
+fn write_text(
+    out: &mut impl Write,
+    event: &Event,
+    nickname: &str,
+    address: &SocketAddr,
+) -> io::Result<()> {
+    match event {
+        Event::MessageReceived(message) | Event::MessageSent { message, .. } => {
+            writeln!(out, "{}", message_line(message))
+        }
+        Event::Delivery {
+            status: DeliveryStatus::Failed(reason),
+            ..
+        } => writeln!(out, "* couldn't send: {reason}"),
+        Event::Connected => writeln!(out, "* connected to {address} as {nickname}"),
+        Event::Disconnected { reason } => writeln!(out, "* disconnected: {reason}"),
+        Event::Reconnecting { attempt, delay } => writeln!(
+            out,
+            "* reconnecting (attempt {attempt}, in {:.1}s)",
+            delay.as_secs_f32()
+        ),
+        Event::UsersList(users) => {
+            writeln!(out, "* {} online: {}", users.len(), users.join(", "))
+        }
+        Event::Delivery { .. } | Event::Quit => Ok(()),
+    }
+}
+
+/// One line of the JSON Lines output of `pipe`
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum JsonEvent<'a> {
+    /// Received, or sent by us if it has an id
+    Message {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<MessageId>,
+        /// Seconds since the Unix epoch
+        time: u64,
+        sender: &'a str,
+        /// `None` for messages to everyone
+        recipient: Option<&'a str>,
+        message: &'a str,
+    },
+    Delivery {
+        id: MessageId,
+        /// pending, sent or failed
+        status: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<&'a str>,
+    },
+    Users {
+        users: &'a [String],
+    },
+    Connected,
+    Disconnected {
+        reason: &'a str,
+    },
+    Reconnecting {
+        attempt: u32,
+        delay_ms: u128,
+    },
+    Quit,
+}
+
+fn write_json(out: &mut impl Write, event: &Event, nickname: &str) -> io::Result<()> {
+    let line = match event {
+        Event::MessageReceived(message) => json_message(None, message, nickname),
+        Event::MessageSent { id, message } => json_message(Some(*id), message, nickname),
+        Event::Delivery { id, status } => {
+            let (status, error) = match status {
+                DeliveryStatus::Pending => ("pending", None),
+                DeliveryStatus::Sent => ("sent", None),
+                DeliveryStatus::Failed(reason) => ("failed", Some(reason.as_str())),
+            };
+            JsonEvent::Delivery {
+                id: *id,
+                status,
+                error,
+            }
+        }
+        Event::UsersList(users) => JsonEvent::Users { users },
+        Event::Connected => JsonEvent::Connected,
+        Event::Disconnected { reason } => JsonEvent::Disconnected { reason },
+        Event::Reconnecting { attempt, delay } => JsonEvent::Reconnecting {
+            attempt: *attempt,
+            delay_ms: delay.as_millis(),
+        },
+        Event::Quit => JsonEvent::Quit,
+    };
+    serde_json::to_writer(&mut *out, &line)?;
+    writeln!(out)
+}
+
+fn json_message<'a>(
+    id: Option<MessageId>,
+    message: &'a MessageInformation,
+    nickname: &'a str,
+) -> JsonEvent<'a> {
+    JsonEvent::Message {
+        id,
+        time: message.time,
+        sender: &message.sender,
+        recipient: match &message.recipient {
+            Recipient::All => None,
+            Recipient::Id(name) => Some(name),
+            Recipient::This => Some(nickname),
+        },
+        message: &message.message,
+    }
+}
+
+/// `[12:34] bela: hi`, `[12:34] [DM] bela: hi` or `[12:34] [DM to bela] anna: hi`, later lines
+/// of the message are indented
+fn message_line(message: &MessageInformation) -> String {
+    let time = chrono::DateTime::from_timestamp(message.time as i64, 0)
+        .unwrap_or_default()
+        .with_timezone(&chrono::Local)
+        .format("%H:%M");
+    let direct = match &message.recipient {
+        Recipient::All => String::new(),
+        Recipient::This => "[DM] ".to_owned(),
+        Recipient::Id(name) => format!("[DM to {name}] "),
+    };
+    let text = message.message.replace('\n', "\n    ");
+    format!("[{time}] {direct}{}: {text}", message.sender)
+}
+
+fn export(
+    config: &Config,
+    format: Format,
+    path: &Path,
+    conversation: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let (nickname, address) = target(config)?;
+    let only = conversation.map(|name| match name {
+        "everyone" => Recipient::All,
+        name => Recipient::Id(name.to_owned()),
+    });
+    let root = ChatLog::default_root().ok_or("There is no data directory for the chat logs")?;
+    // The log is kept under the resolved address, like when connecting
+    let log = ChatLog::open(&root, &address.to_string(), &nickname)?;
+    let transcripts = export::from_log(&log, only.as_ref())?;
+    if path.as_os_str() == "-" {
+        export::write(format, &transcripts, &nickname, &mut io::stdout().lock())?;
+    } else {
+        export::export_to_file(format, &transcripts, &nickname, path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("jedlikchat-tui").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn options_override_the_config() {
+        let mut config = Config {
+            nickname: Some("anna".into()),
+            default_server: Some("home".into()),
+            servers: vec![ServerProfile {
+                name: "home".into(),
+                host: "localhost".into(),
+                port: 5000,
+                nickname: Some("Kiss Anna".into()),
+            }],
+            ..Default::default()
+        };
+        let cli = parse(&["--server", "10.0.0.5:6000", "listen"]);
+        assert!(cli.connects());
+        cli.apply(&mut config).unwrap();
+        let profile = config.default_profile().unwrap();
+        assert_eq!((profile.host.as_str(), profile.port), ("10.0.0.5", 6000));
+        assert_eq!(config.default_nickname(), Some("anna"));
+
+        let cli = parse(&["--profile", "home", "--nick", "bela"]);
+        cli.apply(&mut config).unwrap();
+        assert_eq!(config.default_profile().unwrap().name, "home");
+        assert_eq!(config.default_nickname(), Some("bela"));
+        assert!(cli.command.is_none());
+
+        assert!(parse(&["--profile", "work"]).apply(&mut config).is_err());
+        assert!(parse(&["--server", "localhost"])
+            .apply(&mut config)
+            .is_err());
+        assert!(parse(&["--nick", "a:b"]).apply(&mut config).is_err());
+
+        config.reconnect.enabled = false;
+        parse(&["--reconnect-attempts", "3"])
+            .apply(&mut config)
+            .unwrap();
+        assert_eq!(config.reconnect.policy().max_attempts, Some(3));
+    }
+
+    #[test]
+    fn subcommands() {
+        let cli = parse(&[
+            "send",
+            "--to",
+            "all",
+            "hello",
+            "there",
+            "--log-level",
+            "debug",
+        ]);
+        assert_eq!(cli.log_level, LevelFilter::Debug);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Send { to, text }) if to == "all" && text == ["hello", "there"]
+        ));
+        let cli = parse(&["export", "md", "-", "--conversation", "bela"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Export { format: Format::Markdown, conversation: Some(name), .. })
+                if name == "bela"
+        ));
+        let no_target = target(&Config::default()).unwrap_err();
+        assert!(no_target.starts_with("No server given"));
+        assert!(
+            Cli::try_parse_from(["jedlikchat-tui", "--server", "a:1", "--profile", "b"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["jedlikchat-tui", "send", "--to", "all"]).is_err());
+    }
+
+    #[test]
+    fn messages_are_one_line_each() {
+        let message = MessageInformation {
+            sender: "bela".into(),
+            recipient: Recipient::This,
+            message: "one\ntwo".into(),
+            time: 0,
+        };
+        let line = message_line(&message);
+        assert!(line.ends_with("] [DM] bela: one\n    two"));
+    }
+
+    #[test]
+    fn at_prefix_sends_a_direct_message() {
+        assert_eq!(
+            parse_line("@bela hi there"),
+            (Recipient::Id("bela".into()), "hi there")
+        );
+        assert_eq!(
+            parse_line("build #42 failed"),
+            (Recipient::All, "build #42 failed")
+        );
+        for line in ["@bela", "@ hi", "@a:b hi", "@bela "] {
+            assert_eq!(parse_line(line), (Recipient::All, line));
+        }
+    }
+
+    #[test]
+    fn events_as_json_lines() {
+        let mut out = vec![];
+        let sent = MessageInformation {
+            sender: "anna".into(),
+            recipient: Recipient::Id("bela".into()),
+            message: "hi".into(),
+            time: 7,
+        };
+        let events = [
+            Event::MessageSent {
+                id: 3,
+                message: sent.clone(),
+            },
+            Event::Delivery {
+                id: 3,
+                status: DeliveryStatus::Failed("broken pipe".into()),
+            },
+            Event::MessageReceived(MessageInformation {
+                sender: "bela".into(),
+                recipient: Recipient::This,
+                ..sent
+            }),
+            Event::Reconnecting {
+                attempt: 2,
+                delay: Duration::from_millis(1500),
+            },
+            Event::Connected,
+        ];
+        for event in &events {
+            write_json(&mut out, event, "anna").unwrap();
+        }
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                serde_json::json!({"event": "message", "id": 3, "time": 7, "sender": "anna",
+                    "recipient": "bela", "message": "hi"}),
+                serde_json::json!({"event": "delivery", "id": 3, "status": "failed",
+                    "error": "broken pipe"}),
+                serde_json::json!({"event": "message", "time": 7, "sender": "bela",
+                    "recipient": "anna", "message": "hi"}),
+                serde_json::json!({"event": "reconnecting", "attempt": 2, "delay_ms": 1500}),
+                serde_json::json!({"event": "connected"}),
+            ]
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use super::{Args, Command, Registry};
+use crate::application::ActiveEventLoop;
+use crate::export::{self, Format};
+use crate::networking::{protocol, Recipient};
+use crate::widgets::timestamp::TimestampFormat;
+use crate::{check_connect_form, resolve, App, ConnectionStatus};
+use std::path::Path;
+
+pub fn register(registry: &mut Registry) {
+    let commands = [
+        Command {
+            name: "msg",
+            usage: "<user> <text>",
+            help: "Sends a direct message",
+            run: msg,
+        },
+        Command {
+            name: "all",
+            usage: "<text>",
+            help: "Sends a message to everyone",
+            run: all,
+        },
+        Command {
+            name: "nick",
+            usage: "<name>",
+            help: "Changes your nickname, reconnects if connected",
+            run: nick,
+        },
+        Command {
+            name: "search",
+            usage: "[query]",
+            help: "Searches the history, like Ctrl+F",
+            run: search,
+        },
+        Command {
+            name: "export",
+            usage: "<markdown|html|jsonl|text> <path> [all]",
+            help: "Saves the conversation, or with all every conversation, to a file",
+            run: export,
+        },
+        Command {
+            name: "timestamps",
+            usage: "<hh:mm|hh:mm:ss|relative>",
+            help: "Changes how the times of messages are shown",
+            run: timestamps,
+        },
+        Command {
+            name: "users",
+            usage: "",
+            help: "Lists the users online",
+            run: users,
+        },
+        Command {
+            name: "clear",
+            usage: "",
+            help: "Clears the message history",
+            run: clear,
+        },
+        Command {
+            name: "connect",
+            usage: "<host:port|profile>",
+            help: "Connects to a server or a saved one from the config, leaving the current one",
+            run: connect,
+        },
+        Command {
+            name: "disconnect",
+            usage: "",
+            help: "Leaves the server",
+            run: disconnect,
+        },
+        Command {
+            name: "quit",
+            usage: "",
+            help: "Exits the application",
+            run: quit,
+        },
+        Command {
+            name: "help",
+            usage: "",
+            help: "Shows this list",
+            run: help,
+        },
+    ];
+    for command in commands {
+        registry.register(command);
+    }
+}
+
+fn msg(_: &mut App, event_loop: &mut ActiveEventLoop, mut args: Args) -> Result<(), String> {
+    let to = args.word()?.to_owned();
+    let text = args.text()?;
+    // The name goes in the frame as is, a separator in it would split the frame wrong
+    if !protocol::is_valid_nickname(&to) {
+        return Err("Nicknames can't contain ':' or ','".to_owned());
+    }
+    event_loop
+        .send_message(Recipient::Id(to), text)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn all(_: &mut App, event_loop: &mut ActiveEventLoop, args: Args) -> Result<(), String> {
+    event_loop
+        .send_message(Recipient::All, args.text()?)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn nick(app: &mut App, event_loop: &mut ActiveEventLoop, mut args: Args) -> Result<(), String> {
+    let name = args.word()?.to_owned();
+    args.end()?;
+    if !protocol::is_valid_nickname(&name) {
+        return Err("Nicknames can't contain ':' or ','".to_owned());
+    }
+    app.username.set_value(&name);
+    // The server only learns our name when connecting
+    if let Some(address) = app.server {
+        app.start_session(event_loop, &name, move || Ok(address))?;
+        app.system_line(format!("Reconnecting as {name}"));
+    }
+    Ok(())
+}
+
+fn search(app: &mut App, _: &mut ActiveEventLoop, args: Args) -> Result<(), String> {
+    app.open_search(args.text().unwrap_or_default());
+    Ok(())
+}
+
+fn export(app: &mut App, _: &mut ActiveEventLoop, mut args: Args) -> Result<(), String> {
+    let format: Format = args.word()?.parse()?;
+    let path = Path::new(args.word()?);
+    let only = match args.optional_word()? {
+        None => Some(app.conversations.active().recipient.clone()),
+        Some("all") => None,
+        Some(_) => return Err(args.usage_error()),
+    };
+    args.end()?;
+    let transcripts = export::from_history(&app.conversations, app.log.as_ref(), only.as_ref())
+        .map_err(|e| format!("Couldn't read the chat log: {e}"))?;
+    export::export_to_file(format, &transcripts, app.username.value(), path)
+        .map_err(|e| format!("Couldn't export to {}: {e}", path.display()))?;
+    let count: usize = transcripts.iter().map(|t| t.messages.len()).sum();
+    app.system_line(format!("Exported {count} messages to {}", path.display()));
+    Ok(())
+}
+
+fn timestamps(app: &mut App, _: &mut ActiveEventLoop, mut args: Args) -> Result<(), String> {
+    let format: TimestampFormat = args.word()?.parse()?;
+    args.end()?;
+    app.timestamps = format;
+    Ok(())
+}
+
+fn users(app: &mut App, _: &mut ActiveEventLoop, args: Args) -> Result<(), String> {
+    args.end()?;
+    let line = format!("{} online: {}", app.users.len(), app.users.join(", "));
+    app.system_line(line);
+    Ok(())
+}
+
+fn clear(app: &mut App, _: &mut ActiveEventLoop, args: Args) -> Result<(), String> {
+    args.end()?;
+    app.clear_history();
+    Ok(())
+}
+
+fn connect(app: &mut App, event_loop: &mut ActiveEventLoop, mut args: Args) -> Result<(), String> {
+    let server = args.word()?;
+    let usage = args.usage_error();
+    args.end()?;
+    let (name, host, port) = match app.config.server(server) {
+        Some(profile) => {
+            let name = profile.nickname.as_deref().unwrap_or(app.username.value());
+            check_connect_form(name, &profile.host, &profile.port.to_string())?
+        }
+        None => {
+            let (host, port) = server.rsplit_once(':').ok_or(usage)?;
+            check_connect_form(app.username.value(), host, port)?
+        }
+    };
+    let line = format!("Connecting to {host}:{port}");
+    app.start_session(event_loop, &name, move || resolve(&host, port))?;
+    app.system_line(line);
+    Ok(())
+}
+
+fn disconnect(app: &mut App, event_loop: &mut ActiveEventLoop, args: Args) -> Result<(), String> {
+    args.end()?;
+    if app.server.take().is_none() {
+        return Err("Not connected".to_owned());
+    }
+    event_loop.stop_network_session();
+    app.users.clear();
+    app.connection = ConnectionStatus::Disconnected("disconnected".to_owned());
+    Ok(())
+}
+
+fn quit(_: &mut App, event_loop: &mut ActiveEventLoop, args: Args) -> Result<(), String> {
+    args.end()?;
+    event_loop.exit();
+    Ok(())
+}
+
+fn help(app: &mut App, _: &mut ActiveEventLoop, args: Args) -> Result<(), String> {
+    args.end()?;
+    let lines: Vec<_> = app
+        .commands
+        .iter()
+        .map(|command| match command.usage {
+            "" => format!("/{} - {}", command.name, command.help),
+            usage => format!("/{} {usage} - {}", command.name, command.help),
+        })
+        .collect();
+    for line in lines {
+        app.system_line(line);
+    }
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use crate::application::ActiveEventLoop;
+use crate::App;
+
+mod builtin;
+
+/// Runs a command, the returned error is shown in the history
+pub type Handler = fn(&mut App, &mut ActiveEventLoop, Args) -> Result<(), String>;
+
+/// A slash command that can be typed into the composer
+pub struct Command {
+    pub name: &'static str,
+    /// Arguments as shown in the help, like `<user> <text>`
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub run: Handler,
+}
+
+/// The commands known to the composer, in the order they are listed in the help
+#[derive(Default)]
+pub struct Registry {
+    commands: Vec<Command>,
+}
+
+impl Registry {
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        builtin::register(&mut registry);
+        registry
+    }
+
+    /// Adds a command, replacing the one with the same name if there is one
+    pub fn register(&mut self, command: Command) {
+        match self.commands.iter_mut().find(|c| c.name == command.name) {
+            Some(existing) => *existing = command,
+            None => self.commands.push(command),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.commands.iter()
+    }
+
+    /// Finds the command a line like `/msg bela hi` calls, returns its handler and arguments
+    pub fn parse<'a>(&self, line: &'a str) -> Result<(Handler, Args<'a>), String> {
+        let line = line.strip_prefix('/').unwrap_or(line);
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let command = self
+            .commands
+            .iter()
+            .find(|command| command.name == name)
+            .ok_or_else(|| format!("Unknown command /{name}, see /help"))?;
+        Ok((
+            command.run,
+            Args {
+                rest,
+                name: command.name,
+                usage: command.usage,
+            },
+        ))
+    }
+}
+
+/// Whether the composer should run `line` instead of sending it, `//` sends a single `/`
+pub fn is_command(line: &str) -> bool {
+    line.starts_with('/') && !line.starts_with("//")
+}
+
+/// The not yet consumed arguments of a command
+#[derive(Debug)]
+pub struct Args<'a> {
+    rest: &'a str,
+    name: &'static str,
+    usage: &'static str,
+}
+
+impl<'a> Args<'a> {
+    fn usage_error(&self) -> String {
+        format!("Usage: /{} {}", self.name, self.usage)
+            .trim_end()
+            .to_owned()
+    }
+
+    /// The next argument, nicknames with spaces can be put in double quotes
+    pub fn word(&mut self) -> Result<&'a str, String> {
+        let rest = self.rest.trim_start();
+        let (word, rest) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted
+                .split_once('"')
+                .ok_or_else(|| format!("Missing closing quote. {}", self.usage_error()))?,
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        if word.is_empty() {
+            return Err(self.usage_error());
+        }
+        self.rest = rest;
+        Ok(word)
+    }
+
+    /// The next argument if there is one left
+    pub fn optional_word(&mut self) -> Result<Option<&'a str>, String> {
+        if self.rest.trim().is_empty() {
+            return Ok(None);
+        }
+        self.word().map(Some)
+    }
+
+    /// Everything that's left as is, it can't be empty
+    pub fn text(self) -> Result<&'a str, String> {
+        let text = self.rest.trim_start_matches(' ');
+        if text.trim().is_empty() {
+            return Err(self.usage_error());
+        }
+        Ok(text)
+    }
+
+    /// Fails if there are arguments left
+    pub fn end(self) -> Result<(), String> {
+        if !self.rest.trim().is_empty() {
+            return Err(self.usage_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(rest: &str) -> Args<'_> {
+        Args {
+            rest,
+            name: "msg",
+            usage: "<user> <text>",
+        }
+    }
+
+    #[test]
+    fn words_and_text() {
+        let mut msg = args(" \"Kiss Anna\"  hello  there");
+        assert_eq!(msg.word(), Ok("Kiss Anna"));
+        assert_eq!(msg.text(), Ok("hello  there"));
+
+        let mut msg = args("bela hi\nsecond line");
+        assert_eq!(msg.word(), Ok("bela"));
+        assert_eq!(msg.text(), Ok("hi\nsecond line"));
+
+        let mut msg = args("bela");
+        assert_eq!(msg.word(), Ok("bela"));
+        assert_eq!(msg.text(), Err("Usage: /msg <user> <text>".to_owned()));
+
+        assert!(args("\"Kiss Anna hi").word().is_err());
+        assert!(args("  ").word().is_err());
+        assert_eq!(args(" ").end(), Ok(()));
+        assert!(args("extra").end().is_err());
+
+        let mut optional = args(" out.md ");
+        assert_eq!(optional.optional_word(), Ok(Some("out.md")));
+        assert_eq!(optional.optional_word(), Ok(None));
+    }
+
+    #[test]
+    fn lookup() {
+        let registry = Registry::with_builtins();
+        let (_, mut args) = registry.parse("/msg bela hi").unwrap();
+        assert_eq!(args.word(), Ok("bela"));
+        assert!(registry.parse("/quit").is_ok());
+        assert_eq!(
+            registry.parse("/frobnicate now").err(),
+            Some("Unknown command /frobnicate, see /help".to_owned())
+        );
+        for name in [
+            "msg",
+            "all",
+            "nick",
+            "export",
+            "timestamps",
+            "users",
+            "clear",
+            "connect",
+            "disconnect",
+            "quit",
+            "help",
+        ] {
+            assert!(registry.iter().any(|command| command.name == name));
+        }
+        assert!(is_command("/help"));
+        assert!(!is_command("//not a command"));
+        assert!(!is_command("hi /all"));
+    }
+
+    #[test]
+    fn register_replaces_by_name() {
+        let mut registry = Registry::with_builtins();
+        let count = registry.iter().count();
+        registry.register(Command {
+            name: "quit",
+            usage: "",
+            help: "Does nothing",
+            run: |_, _, args| args.end(),
+        });
+        registry.register(Command {
+            name: "me",
+            usage: "<text>",
+            help: "Describes what you do",
+            run: |_, _, args| args.text().map(|_| ()),
+        });
+        assert_eq!(registry.iter().count(), count + 1);
+        let quit = registry.iter().find(|c| c.name == "quit").unwrap();
+        assert_eq!(quit.help, "Does nothing");
+    }
+}
@@ -0,0 +1,118 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::str::FromStr;
+
+/// A key with its modifiers, written like `ctrl+f` or `alt+enter` in the config
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    pub const fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        Self { code, modifiers }
+    }
+
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        // Terminals report shifted letters in upper case, with or without the shift modifier
+        let code = match key.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        code == self.code && key.modifiers == self.modifiers
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lowercase = s.to_lowercase();
+        // The key can be `+` itself, like in `ctrl++`
+        let (modifiers, key) = match lowercase.strip_suffix("++") {
+            Some(modifiers) => (modifiers, "+"),
+            None => lowercase.rsplit_once('+').unwrap_or(("", &lowercase)),
+        };
+        let mut binding = KeyBinding::new(key_code(key)?, KeyModifiers::NONE);
+        for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+            binding.modifiers |= match modifier {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("Unknown modifier {modifier} in key {s}")),
+            };
+        }
+        Ok(binding)
+    }
+}
+
+fn key_code(key: &str) -> Result<KeyCode, String> {
+    let code = match key {
+        "enter" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "space" => KeyCode::Char(' '),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        key => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => match key.strip_prefix('f').map(str::parse) {
+                    Some(Ok(n @ 1..=24)) => KeyCode::F(n),
+                    _ => return Err(format!("Unknown key {key}")),
+                },
+            }
+        }
+    };
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "Ctrl+F".parse(),
+            Ok(KeyBinding::new(KeyCode::Char('f'), KeyModifiers::CONTROL))
+        );
+        assert_eq!(
+            "ctrl+shift+pagedown".parse(),
+            Ok(KeyBinding::new(
+                KeyCode::PageDown,
+                KeyModifiers::CONTROL | KeyModifiers::SHIFT
+            ))
+        );
+        assert_eq!(
+            "alt++".parse(),
+            Ok(KeyBinding::new(KeyCode::Char('+'), KeyModifiers::ALT))
+        );
+        assert_eq!(
+            "f5".parse(),
+            Ok(KeyBinding::new(KeyCode::F(5), KeyModifiers::NONE))
+        );
+        assert_eq!(
+            "hyper+x".parse::<KeyBinding>(),
+            Err("Unknown modifier hyper in key hyper+x".to_owned())
+        );
+        assert!("ctrl+nope".parse::<KeyBinding>().is_err());
+        assert!("f99".parse::<KeyBinding>().is_err());
+    }
+
+    #[test]
+    fn matches_ignore_letter_case() {
+        let binding: KeyBinding = "ctrl+f".parse().unwrap();
+        assert!(binding.matches(&KeyEvent::new(KeyCode::Char('F'), KeyModifiers::CONTROL)));
+        assert!(!binding.matches(&KeyEvent::new(KeyCode::Char('f'), KeyModifiers::ALT)));
+    }
+}
@@ -0,0 +1,447 @@
+use crate::chat_log::Retention;
+use crate::networking::{protocol, ReconnectPolicy};
+use crate::widgets::timestamp::TimestampFormat;
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::style::Color;
+use serde::{Deserialize, Deserializer};
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+mod keys;
+
+pub use keys::KeyBinding;
+
+/// Points to the config file, used when no path is given on the command line
+pub const PATH_VARIABLE: &str = "JEDLIKCHAT_CONFIG";
+
+/// Everything in the config file, each part falls back to its defaults if it's missing
+#[derive(Debug, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Put in the connect form on launch
+    pub nickname: Option<String>,
+    /// Name of the server profile put in the connect form on launch
+    pub default_server: Option<String>,
+    pub servers: Vec<ServerProfile>,
+    pub theme: Theme,
+    pub keybindings: Keybindings,
+    #[serde(deserialize_with = "parsed")]
+    pub timestamp_format: TimestampFormat,
+    pub log: LogConfig,
+    pub reconnect: ReconnectConfig,
+    pub notifications: Notifications,
+}
+
+/// A saved server, `/connect` takes its name instead of an address
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerProfile {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    /// Used instead of the global nickname on this server
+    pub nickname: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// The focused pane and the selection in lists
+    #[serde(deserialize_with = "color")]
+    pub accent: Color,
+    /// Panes without focus
+    #[serde(deserialize_with = "color")]
+    pub border: Color,
+    #[serde(deserialize_with = "color")]
+    pub direct: Color,
+    /// Lines of the client itself, like command output
+    #[serde(deserialize_with = "color")]
+    pub system: Color,
+    #[serde(deserialize_with = "color")]
+    pub timestamp: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            accent: Color::LightGreen,
+            border: Color::Green,
+            direct: Color::Magenta,
+            system: Color::Yellow,
+            timestamp: Color::DarkGray,
+        }
+    }
+}
+
+/// Keys of the actions that aren't tied to a pane, each can have several
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keybindings {
+    #[serde(deserialize_with = "key_list")]
+    pub quit: Vec<KeyBinding>,
+    #[serde(deserialize_with = "key_list")]
+    pub search: Vec<KeyBinding>,
+    #[serde(deserialize_with = "key_list")]
+    pub next_tab: Vec<KeyBinding>,
+    #[serde(deserialize_with = "key_list")]
+    pub previous_tab: Vec<KeyBinding>,
+    /// Starts a new line in the composer instead of sending
+    #[serde(deserialize_with = "key_list")]
+    pub newline: Vec<KeyBinding>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            quit: vec![KeyBinding::new(KeyCode::Esc, KeyModifiers::NONE)],
+            search: vec![KeyBinding::new(KeyCode::Char('f'), KeyModifiers::CONTROL)],
+            next_tab: vec![KeyBinding::new(KeyCode::PageDown, KeyModifiers::CONTROL)],
+            previous_tab: vec![KeyBinding::new(KeyCode::PageUp, KeyModifiers::CONTROL)],
+            // Shift+Enter needs a terminal that tells it apart from Enter
+            newline: vec![
+                KeyBinding::new(KeyCode::Enter, KeyModifiers::SHIFT),
+                KeyBinding::new(KeyCode::Enter, KeyModifiers::ALT),
+            ],
+        }
+    }
+}
+
+/// The `[log]` table, turned into a [`Retention`]
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub enabled: bool,
+    /// 0 keeps messages forever
+    pub max_age_days: u64,
+    /// 0 keeps every message
+    pub max_messages: usize,
+    pub restore: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_age_days: 90,
+            max_messages: 0,
+            restore: 100,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn retention(&self) -> Retention {
+        Retention {
+            enabled: self.enabled,
+            max_age: (self.max_age_days > 0)
+                .then(|| Duration::from_secs(self.max_age_days * 24 * 60 * 60)),
+            max_messages: (self.max_messages > 0).then_some(self.max_messages),
+            restore: self.restore,
+        }
+    }
+}
+
+/// The `[reconnect]` table, turned into a [`ReconnectPolicy`]
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectConfig {
+    pub enabled: bool,
+    /// 0 never gives up
+    pub max_attempts: u32,
+    /// Before the first attempt, doubled for every following one
+    pub initial_delay_ms: u64,
+    pub max_delay_secs: u64,
+    /// Fraction of the delay that is randomly added or taken away, between 0 and 1
+    pub jitter: f64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        let policy = ReconnectPolicy::default();
+        Self {
+            enabled: true,
+            max_attempts: policy.max_attempts.unwrap_or(0),
+            initial_delay_ms: policy.initial_delay.as_millis() as u64,
+            max_delay_secs: policy.max_delay.as_secs(),
+            jitter: policy.jitter,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    pub fn policy(&self) -> ReconnectPolicy {
+        if !self.enabled {
+            return ReconnectPolicy::disabled();
+        }
+        ReconnectPolicy {
+            max_attempts: (self.max_attempts > 0).then_some(self.max_attempts),
+            initial_delay: Duration::from_millis(self.initial_delay_ms),
+            max_delay: Duration::from_secs(self.max_delay_secs),
+            jitter: self.jitter,
+        }
+    }
+}
+
+/// When to ring the terminal bell for a received message
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notifications {
+    pub direct_messages: bool,
+    /// Messages to everyone that mention our nickname
+    pub mentions: bool,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self {
+            direct_messages: true,
+            mentions: true,
+        }
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/jedlikchat/config.toml` or `~/.config/jedlikchat/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("jedlikchat").join("config.toml"))
+    }
+
+    /// Loads the file at `path`, or where [`PATH_VARIABLE`] points, or at the default path.
+    ///
+    /// Only a missing file at the default path gives the defaults, one that was asked for has
+    /// to exist.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let variable = std::env::var_os(PATH_VARIABLE).map(PathBuf::from);
+        let (path, required) = match path.map(Path::to_owned).or(variable) {
+            Some(path) => (path, true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(format!("Couldn't read the config {}: {e}", path.display())),
+        };
+        Self::parse(&text).map_err(|e| format!("Invalid config {}: {e}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks what the types alone can't
+    fn validate(&self) -> Result<(), String> {
+        let nicknames = self
+            .servers
+            .iter()
+            .filter_map(|server| server.nickname.as_ref())
+            .chain(&self.nickname);
+        for nickname in nicknames {
+            if !protocol::is_valid_nickname(nickname) {
+                return Err(format!(
+                    "nickname {nickname:?} has to be non-empty without ':' or ','"
+                ));
+            }
+        }
+        for (i, server) in self.servers.iter().enumerate() {
+            if self.servers[..i].iter().any(|s| s.name == server.name) {
+                return Err(format!("there are two servers named {}", server.name));
+            }
+        }
+        if let Some(name) = &self.default_server {
+            if self.server(name).is_none() {
+                return Err(format!("default_server {name} isn't in servers"));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.reconnect.jitter) {
+            return Err("reconnect.jitter has to be between 0 and 1".to_owned());
+        }
+        Ok(())
+    }
+
+    pub fn server(&self, name: &str) -> Option<&ServerProfile> {
+        self.servers.iter().find(|server| server.name == name)
+    }
+
+    pub fn default_profile(&self) -> Option<&ServerProfile> {
+        self.server(self.default_server.as_deref()?)
+    }
+
+    /// The nickname to use on the default server
+    pub fn default_nickname(&self) -> Option<&str> {
+        self.default_profile()
+            .and_then(|profile| profile.nickname.as_deref())
+            .or(self.nickname.as_deref())
+    }
+}
+
+/// Reads a string and parses it with [`FromStr`], like timestamp formats
+fn parsed<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map_err(serde::de::Error::custom)
+}
+
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    text.parse().map_err(|_| {
+        serde::de::Error::custom(format!(
+            "unknown color {text}, use a name like lightgreen or #rrggbb"
+        ))
+    })
+}
+
+/// A single key like `"ctrl+f"` or a list of them
+fn key_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<KeyBinding>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Keys {
+        One(String),
+        Many(Vec<String>),
+    }
+    let keys = match Keys::deserialize(deserializer)? {
+        Keys::One(key) => vec![key],
+        Keys::Many(keys) => keys,
+    };
+    keys.iter()
+        .map(|key| key.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_file_is_the_defaults() {
+        assert_eq!(Config::parse(""), Ok(Config::default()));
+        assert_eq!(Config::default().log.retention(), Retention::default());
+        assert_eq!(
+            Config::default().reconnect.policy(),
+            ReconnectPolicy::default()
+        );
+    }
+
+    #[test]
+    fn full_config() {
+        let config = Config::parse(
+            r##"
+            nickname = "Kiss Anna"
+            default_server = "school"
+            timestamp_format = "hh:mm:ss"
+
+            [[servers]]
+            name = "school"
+            host = "10.0.0.5"
+            port = 5000
+
+            [[servers]]
+            name = "home"
+            host = "localhost"
+            port = 6000
+            nickname = "anna"
+
+            [theme]
+            accent = "#ff8800"
+            direct = "cyan"
+
+            [keybindings]
+            quit = "ctrl+q"
+            newline = ["alt+enter", "ctrl+j"]
+
+            [log]
+            max_age_days = 0
+            max_messages = 500
+
+            [reconnect]
+            max_attempts = 0
+            max_delay_secs = 5
+
+            [notifications]
+            mentions = false
+            "##,
+        )
+        .unwrap();
+        assert_eq!(config.nickname.as_deref(), Some("Kiss Anna"));
+        assert_eq!(config.server("home").unwrap().port, 6000);
+        assert_eq!(config.timestamp_format, TimestampFormat::Seconds);
+        assert_eq!(config.theme.accent, Color::Rgb(0xff, 0x88, 0x00));
+        assert_eq!(config.theme.direct, Color::Cyan);
+        assert_eq!(config.theme.system, Color::Yellow);
+        assert_eq!(
+            config.keybindings.quit,
+            [KeyBinding::new(KeyCode::Char('q'), KeyModifiers::CONTROL)]
+        );
+        assert_eq!(config.keybindings.newline.len(), 2);
+        assert_eq!(config.keybindings.search, Keybindings::default().search);
+        let retention = config.log.retention();
+        assert_eq!(retention.max_age, None);
+        assert_eq!(retention.max_messages, Some(500));
+        let policy = config.reconnect.policy();
+        assert_eq!(policy.max_attempts, None);
+        assert_eq!(policy.max_delay, Duration::from_secs(5));
+        assert_eq!(
+            policy.initial_delay,
+            ReconnectPolicy::default().initial_delay
+        );
+        let disabled = Config::parse("[reconnect]\nenabled = false").unwrap();
+        assert_eq!(disabled.reconnect.policy(), ReconnectPolicy::disabled());
+        assert!(config.notifications.direct_messages);
+        assert!(!config.notifications.mentions);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let error = |text| Config::parse(text).unwrap_err();
+        assert!(error("nickname = 5").contains("line 1"));
+        assert!(error("nick = \"anna\"").contains("unknown field `nick`"));
+        let color = error("[theme]\naccent = \"blurple\"");
+        assert!(color.contains("line 2") && color.contains("unknown color blurple"));
+        assert!(error("[keybindings]\nsearch = \"ctrl+nope\"").contains("Unknown key nope"));
+        assert!(error("timestamp_format = \"iso\"").contains("Unknown timestamp format"));
+        assert_eq!(
+            error("default_server = \"nowhere\""),
+            "default_server nowhere isn't in servers"
+        );
+        assert!(error("nickname = \"a:b\"").contains("without ':'"));
+        assert_eq!(
+            error("[reconnect]\njitter = 2.0"),
+            "reconnect.jitter has to be between 0 and 1"
+        );
+        let twice = "[[servers]]\nname = \"a\"\nhost = \"h\"\nport = 1\n";
+        assert_eq!(
+            error(&format!("{twice}{twice}")),
+            "there are two servers named a"
+        );
+    }
+
+    #[test]
+    fn load_from_a_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        assert!(Config::load(Some(&path))
+            .unwrap_err()
+            .starts_with("Couldn't read the config"));
+        std::fs::write(&path, "port = 1").unwrap();
+        let error = Config::load(Some(&path)).unwrap_err();
+        assert!(error.starts_with(&format!("Invalid config {}: ", path.display())));
+        std::fs::write(&path, "nickname = \"anna\"").unwrap();
+        assert_eq!(
+            Config::load(Some(&path)).unwrap().nickname.as_deref(),
+            Some("anna")
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use crate::config::Theme;
+use crate::networking::{DeliveryStatus, MessageId, MessageInformation, Recipient};
+use crate::widgets::message_view::MessageViewState;
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::Tabs;
+
+pub enum HistoryEntry {
+    Message {
+        message: MessageInformation,
+        /// Only set for our own messages
+        delivery: Option<(MessageId, DeliveryStatus)>,
+    },
+    /// Output of the client itself, like command results and errors
+    System(String),
+}
+
+/// The conversation a message belongs to, broadcasts go to everyone and DMs to the other party
+pub fn conversation_of(message: &MessageInformation) -> Recipient {
+    match &message.recipient {
+        Recipient::This => Recipient::Id(message.sender.clone()),
+        recipient => recipient.clone(),
+    }
+}
+
+/// Whether `message` mentions `nickname`, ignoring case
+pub fn mentions(message: &str, nickname: &str) -> bool {
+    !nickname.is_empty() && message.to_lowercase().contains(&nickname.to_lowercase())
+}
+
+pub struct Conversation {
+    /// [`Recipient::All`] for everyone, [`Recipient::Id`] with the other party for DMs
+    pub recipient: Recipient,
+    pub history: Vec<HistoryEntry>,
+    pub view: MessageViewState,
+    /// Messages received while the conversation wasn't the active one
+    pub unread: usize,
+    /// The unread messages mentioning us
+    pub mentions: usize,
+    /// Older messages that are only in the chat log, they come right before the history
+    pub unloaded: usize,
+}
+
+impl Conversation {
+    fn new(recipient: Recipient) -> Self {
+        Self {
+            recipient,
+            history: vec![],
+            view: MessageViewState::default(),
+            unread: 0,
+            mentions: 0,
+            unloaded: 0,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        let mut title = match &self.recipient {
+            Recipient::Id(name) => name.clone(),
+            Recipient::All | Recipient::This => "Everyone".to_owned(),
+        };
+        if self.unread > 0 {
+            title += &format!(" ({})", self.unread);
+        }
+        if self.mentions > 0 {
+            title += &format!(" @{}", self.mentions);
+        }
+        title
+    }
+}
+
+/// The open conversations shown as tabs, everyone first and DMs in the order they started
+pub struct Conversations {
+    tabs: Vec<Conversation>,
+    active: usize,
+}
+
+impl Default for Conversations {
+    fn default() -> Self {
+        Self {
+            tabs: vec![Conversation::new(Recipient::All)],
+            active: 0,
+        }
+    }
+}
+
+impl Conversations {
+    pub fn active(&self) -> &Conversation {
+        &self.tabs[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut Conversation {
+        &mut self.tabs[self.active]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Conversation> {
+        self.tabs.iter()
+    }
+
+    /// Index of the conversation with `recipient`, opening it if there is none yet
+    fn open(&mut self, recipient: &Recipient) -> usize {
+        let recipient = match recipient {
+            Recipient::This => &Recipient::All,
+            recipient => recipient,
+        };
+        match self.tabs.iter().position(|tab| tab.recipient == *recipient) {
+            Some(index) => index,
+            None => {
+                self.tabs.push(Conversation::new(recipient.clone()));
+                self.tabs.len() - 1
+            }
+        }
+    }
+
+    /// Makes the tab at `index` active, returns false if there is no such tab
+    pub fn select(&mut self, index: usize) -> bool {
+        if index >= self.tabs.len() {
+            return false;
+        }
+        self.active = index;
+        let tab = &mut self.tabs[index];
+        tab.unread = 0;
+        tab.mentions = 0;
+        true
+    }
+
+    /// Switches to the conversation with `recipient`, opening it if needed
+    pub fn select_recipient(&mut self, recipient: &Recipient) {
+        let index = self.open(recipient);
+        self.select(index);
+    }
+
+    pub fn select_next(&mut self) {
+        self.select((self.active + 1) % self.tabs.len());
+    }
+
+    pub fn select_previous(&mut self) {
+        self.select((self.active + self.tabs.len() - 1) % self.tabs.len());
+    }
+
+    /// Adds a message to its conversation, received messages count as unread unless that
+    /// conversation is active
+    pub fn push(
+        &mut self,
+        message: MessageInformation,
+        delivery: Option<(MessageId, DeliveryStatus)>,
+        nickname: &str,
+    ) -> &mut Conversation {
+        let index = self.open(&conversation_of(&message));
+        let tab = &mut self.tabs[index];
+        if index != self.active && delivery.is_none() {
+            tab.unread += 1;
+            if mentions(&message.message, nickname) {
+                tab.mentions += 1;
+            }
+        }
+        tab.history
+            .push(HistoryEntry::Message { message, delivery });
+        tab.view.message_added();
+        tab
+    }
+
+    /// Puts messages from the chat log at the start of their conversation, `unloaded` older
+    /// ones are left in the log
+    pub fn restore(
+        &mut self,
+        conversation: &Recipient,
+        messages: impl IntoIterator<Item = MessageInformation>,
+        unloaded: usize,
+    ) {
+        let index = self.open(conversation);
+        let tab = &mut self.tabs[index];
+        let restored = messages.into_iter().map(|message| HistoryEntry::Message {
+            message,
+            delivery: None,
+        });
+        tab.history.splice(0..0, restored);
+        tab.unloaded = unloaded;
+    }
+
+    /// Adds a system line to the active conversation
+    pub fn push_system(&mut self, line: String) {
+        let tab = self.active_mut();
+        tab.history.push(HistoryEntry::System(line));
+        tab.view.message_added();
+    }
+
+    /// Updates the status of one of our own messages
+    pub fn set_delivery(&mut self, id: MessageId, status: DeliveryStatus) {
+        // Our own messages are near the end, no point in scanning whole histories
+        let delivery = self.tabs.iter_mut().find_map(|tab| {
+            tab.history.iter_mut().rev().find_map(|entry| match entry {
+                HistoryEntry::Message {
+                    delivery: Some((sent_id, delivery)),
+                    ..
+                } if *sent_id == id => Some(delivery),
+                _ => None,
+            })
+        });
+        if let Some(delivery) = delivery {
+            *delivery = status;
+        }
+    }
+
+    pub fn tabs(&self, theme: &Theme) -> Tabs<'static> {
+        Tabs::new(self.iter().map(|tab| {
+            let title = Line::from(tab.title());
+            if tab.mentions > 0 {
+                title.style(theme.direct)
+            } else if tab.unread > 0 {
+                title.bold()
+            } else {
+                title
+            }
+        }))
+        .select(self.active)
+        .highlight_style(Style::new().fg(Color::Black).bg(theme.accent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(sender: &str, recipient: Recipient, message: &str) -> MessageInformation {
+        MessageInformation {
+            sender: sender.into(),
+            recipient,
+            message: message.into(),
+            time: 0,
+        }
+    }
+
+    fn titles(conversations: &Conversations) -> Vec<String> {
+        conversations.iter().map(Conversation::title).collect()
+    }
+
+    #[test]
+    fn messages_go_to_their_conversation() {
+        let mut conversations = Conversations::default();
+        conversations.push(received("bela", Recipient::All, "hi all"), None, "anna");
+        conversations.push(received("bela", Recipient::This, "hi Anna"), None, "anna");
+        conversations.push(
+            received("anna", Recipient::Id("cili".into()), "psst"),
+            Some((0, DeliveryStatus::Pending)),
+            "anna",
+        );
+        conversations.push(received("cili", Recipient::This, "what?"), None, "anna");
+        assert_eq!(
+            titles(&conversations),
+            ["Everyone", "bela (1) @1", "cili (1)"]
+        );
+        assert_eq!(conversations.active().history.len(), 1);
+
+        conversations.select_recipient(&Recipient::Id("cili".into()));
+        assert_eq!(conversations.active().history.len(), 2);
+        assert_eq!(titles(&conversations), ["Everyone", "bela (1) @1", "cili"]);
+
+        conversations.set_delivery(0, DeliveryStatus::Sent);
+        assert!(matches!(
+            conversations.active().history[0],
+            HistoryEntry::Message {
+                delivery: Some((0, DeliveryStatus::Sent)),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn restored_messages_come_first_and_are_read() {
+        let mut conversations = Conversations::default();
+        conversations.push(received("bela", Recipient::All, "new"), None, "anna");
+        let dm = Recipient::Id("cili".into());
+        conversations.restore(
+            &Recipient::All,
+            [received("bela", Recipient::All, "old")],
+            3,
+        );
+        conversations.restore(&dm, [received("cili", Recipient::This, "old dm")], 0);
+        assert_eq!(titles(&conversations), ["Everyone", "cili"]);
+        let texts: Vec<_> = conversations
+            .active()
+            .history
+            .iter()
+            .map(|entry| match entry {
+                HistoryEntry::Message { message, .. } => message.message.as_str(),
+                HistoryEntry::System(line) => line,
+            })
+            .collect();
+        assert_eq!(texts, ["old", "new"]);
+        assert_eq!(conversations.active().unloaded, 3);
+    }
+
+    #[test]
+    fn switching_wraps_around() {
+        let mut conversations = Conversations::default();
+        conversations.select_recipient(&Recipient::Id("bela".into()));
+        conversations.select_next();
+        assert_eq!(conversations.active().recipient, Recipient::All);
+        conversations.select_previous();
+        assert_eq!(
+            conversations.active().recipient,
+            Recipient::Id("bela".into())
+        );
+        assert!(!conversations.select(2));
+        assert!(conversations.select(0));
+        assert_eq!(conversations.active().recipient, Recipient::All);
+    }
+
+    #[test]
+    fn mentions_ignore_case() {
+        assert!(mentions("Hey ANNA, look", "anna"));
+        assert!(!mentions("hey", "anna"));
+        assert!(!mentions("hey", ""));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::application::{restore_terminal, GeneralEvent};
+use crate::networking::Event;
+use chrono::{DateTime, Local};
+use color_eyre::config::{HookBuilder, PanicHook};
+use crossterm::event::{Event as Input, KeyCode};
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::panic::PanicHookInfo;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// How many of the latest events go in a crash report
+const RECENT_EVENTS: usize = 50;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+/// Where panics of other threads go while the TUI is running, `None` otherwise
+static EVENT_LOOP: Mutex<Option<Sender<GeneralEvent>>> = Mutex::new(None);
+
+/// A panic can happen while one of the locks is held, the report is still worth writing
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Installs color-eyre and a panic hook that writes a crash report.
+///
+/// Panics on the main thread restore the terminal before the report is printed. Panics on
+/// other threads are sent to the event loop as [`GeneralEvent::Panicked`] while it's running,
+/// so it can shut down properly
+pub fn install() -> color_eyre::Result<()> {
+    let (panic_hook, eyre_hook) = HookBuilder::default().into_hooks();
+    eyre_hook.install()?;
+    set_panic_hook(panic_hook);
+    Ok(())
+}
+
+fn set_panic_hook(panic_hook: PanicHook) {
+    std::panic::set_hook(Box::new(move |info| on_panic(&panic_hook, info)));
+}
+
+/// Sends the panics of other threads to the event loop and restores the terminal on panics,
+/// until [`detach`] is called
+pub fn attach(events: Sender<GeneralEvent>) {
+    *lock(&EVENT_LOOP) = Some(events);
+}
+
+pub fn detach() {
+    *lock(&EVENT_LOOP) = None;
+}
+
+/// Remembers the kind of an event of the event loop for the crash report
+pub fn record(event: &GeneralEvent) {
+    let mut recent = lock(&RECENT);
+    if recent.len() == RECENT_EVENTS {
+        recent.pop_front();
+    }
+    recent.push_back(kind(event));
+}
+
+/// What happened without what was typed or said, reports get shared
+fn kind(event: &GeneralEvent) -> String {
+    match event {
+        GeneralEvent::Networking(event) => {
+            let kind = match event {
+                Event::Quit => "Quit",
+                Event::UsersList(_) => "UsersList",
+                Event::MessageSent { .. } => "MessageSent",
+                Event::Delivery { .. } => "Delivery",
+                Event::MessageReceived(_) => "MessageReceived",
+                Event::Connected => "Connected",
+                Event::Disconnected { .. } => "Disconnected",
+                Event::Reconnecting { .. } => "Reconnecting",
+            };
+            format!("Networking({kind})")
+        }
+        GeneralEvent::Input(Input::Key(key)) => match key.code {
+            KeyCode::Char(_) => format!("Key(Char, {:?})", key.modifiers),
+            code => format!("Key({code:?}, {:?})", key.modifiers),
+        },
+        GeneralEvent::Input(Input::Mouse(mouse)) => format!("Mouse({:?})", mouse.kind),
+        GeneralEvent::Input(Input::Paste(_)) => "Paste".to_owned(),
+        GeneralEvent::Input(input) => format!("{input:?}"),
+        GeneralEvent::RedrawRequested => "RedrawRequested".to_owned(),
+        GeneralEvent::Exit => "Exit".to_owned(),
+        GeneralEvent::SessionStarted(Ok(_)) => "SessionStarted(Ok)".to_owned(),
+        GeneralEvent::SessionStarted(Err(_)) => "SessionStarted(Err)".to_owned(),
+        GeneralEvent::Panicked { .. } => "Panicked".to_owned(),
+    }
+}
+
+fn on_panic(hook: &PanicHook, info: &PanicHookInfo) {
+    let thread = thread::current().name().unwrap_or("unnamed").to_owned();
+    let message = panic_message(info);
+    log::error!("the {thread} thread panicked: {message}");
+    let report = match write_report(&thread, info, &message) {
+        Ok(path) => Some(path),
+        Err(e) => {
+            log::error!("couldn't write the crash report: {e}");
+            None
+        }
+    };
+
+    let event_loop = lock(&EVENT_LOOP).clone();
+    let Some(event_loop) = event_loop else {
+        // Nothing to restore without the TUI
+        eprintln!("{}", hook.panic_report(info));
+        return;
+    };
+    if thread != "main" {
+        let panicked = GeneralEvent::Panicked {
+            thread,
+            message,
+            report: report.clone(),
+        };
+        if event_loop.send(panicked).is_ok() {
+            return;
+        }
+    }
+    restore_terminal();
+    eprintln!("{}", hook.panic_report(info));
+    if let Some(path) = report {
+        eprintln!("A crash report was written to {}", path.display());
+    }
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_owned())
+}
+
+/// `<state dir>/jedlikchat/crashes`, next to the log file
+#[cfg(not(test))]
+fn report_dir() -> Option<PathBuf> {
+    let dir = dirs::state_dir().or_else(dirs::data_dir)?;
+    Some(dir.join("jedlikchat").join("crashes"))
+}
+
+/// Tests panic on purpose, their reports stay out of the real state directory
+#[cfg(test)]
+fn report_dir() -> Option<PathBuf> {
+    Some(std::env::temp_dir().join("jedlikchat-test-crashes"))
+}
+
+fn write_report(thread: &str, info: &PanicHookInfo, message: &str) -> io::Result<PathBuf> {
+    let dir = report_dir().ok_or_else(|| io::Error::other("there is no state directory"))?;
+    fs::create_dir_all(&dir)?;
+    let now = Local::now();
+    let path = dir.join(format!("crash-{}.txt", now.format("%Y%m%d-%H%M%S%.3f")));
+    let location = info
+        .location()
+        .map(|location| location.to_string())
+        .unwrap_or_default();
+    let panic = format!("thread '{thread}' panicked at {location}:\n{message}");
+    let backtrace = Backtrace::force_capture();
+    let recent = lock(&RECENT).clone();
+    write(&mut File::create(&path)?, now, &panic, &backtrace, &recent)?;
+    Ok(path)
+}
+
+fn write(
+    out: &mut impl Write,
+    time: DateTime<Local>,
+    panic: &str,
+    backtrace: &Backtrace,
+    recent: &VecDeque<String>,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{} {} crashed at {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        time.to_rfc3339()
+    )?;
+    writeln!(out, "{} {}", std::env::consts::OS, std::env::consts::ARCH)?;
+    writeln!(out, "\n{panic}\n\nBacktrace:\n{backtrace}")?;
+    writeln!(out, "Last {} events, oldest first:", recent.len())?;
+    for event in recent {
+        writeln!(out, "{event}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::{MessageInformation, Recipient};
+    use crate::utils;
+    use crossterm::event::{KeyEvent, KeyModifiers};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn report_has_the_version_panic_and_recent_events() {
+        for i in 0..RECENT_EVENTS + 5 {
+            let event = if i % 2 == 0 {
+                GeneralEvent::RedrawRequested
+            } else {
+                GeneralEvent::Exit
+            };
+            record(&event);
+        }
+        let recent = lock(&RECENT).clone();
+        assert_eq!(recent.len(), RECENT_EVENTS);
+        assert_eq!(recent.back().unwrap(), "RedrawRequested");
+
+        let mut out = vec![];
+        let panic = "thread 'input' panicked at src/application.rs:1:1:\noops";
+        write(
+            &mut out,
+            Local::now(),
+            panic,
+            &Backtrace::disabled(),
+            &recent,
+        )
+        .unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.starts_with(&format!("jedlikchat-tui {}", env!("CARGO_PKG_VERSION"))));
+        assert!(report.contains(panic));
+        assert!(report.contains(&format!("Last {RECENT_EVENTS} events, oldest first:\n")));
+        assert!(report.ends_with("RedrawRequested\n"));
+    }
+
+    #[test]
+    fn recorded_events_leave_out_keys_and_messages() {
+        let key = KeyEvent::new(KeyCode::Char('p'), KeyModifiers::CONTROL);
+        assert_eq!(
+            kind(&GeneralEvent::Input(Input::Key(key))),
+            format!("Key(Char, {:?})", KeyModifiers::CONTROL)
+        );
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        assert!(kind(&GeneralEvent::Input(Input::Key(enter))).starts_with("Key(Enter, "));
+        let paste = Input::Paste("hunter2".into());
+        assert_eq!(kind(&GeneralEvent::Input(paste)), "Paste");
+        let message = MessageInformation {
+            sender: "bela".into(),
+            recipient: Recipient::This,
+            message: "secret".into(),
+            time: 0,
+        };
+        let received = GeneralEvent::Networking(Event::MessageReceived(message));
+        assert_eq!(kind(&received), "Networking(MessageReceived)");
+        let failed = GeneralEvent::SessionStarted(Err("Couldn't resolve host".into()));
+        assert_eq!(kind(&failed), "SessionStarted(Err)");
+    }
+
+    #[test]
+    fn panics_of_other_threads_go_to_the_event_loop() {
+        set_panic_hook(HookBuilder::default().into_hooks().0);
+        let (events, receiver) = channel();
+        attach(events);
+        let worker = utils::spawn("worker", || panic!("oops"));
+        let event = receiver.recv_timeout(Duration::from_secs(10));
+        detach();
+        drop(std::panic::take_hook());
+        assert!(worker.join().is_err());
+
+        let Ok(GeneralEvent::Panicked {
+            thread,
+            message,
+            report,
+        }) = event
+        else {
+            panic!("expected a panic event, got {event:?}");
+        };
+        assert_eq!((thread.as_str(), message.as_str()), ("worker", "oops"));
+        let path = report.unwrap();
+        let report = fs::read_to_string(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(report.contains("thread 'worker' panicked at src/crash/mod.rs"));
+    }
+}
@@ -0,0 +1,450 @@
+use crate::chat_log::ChatLog;
+use crate::conversations::{Conversations, HistoryEntry};
+use crate::networking::{MessageInformation, Recipient};
+use chrono::{DateTime, Local, TimeZone};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Markdown,
+    Html,
+    JsonLines,
+    Text,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "md" | "markdown" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            "jsonl" | "json" => Ok(Format::JsonLines),
+            "txt" | "text" => Ok(Format::Text),
+            _ => Err(format!(
+                "Unknown export format {s}, use markdown, html, jsonl or text"
+            )),
+        }
+    }
+}
+
+/// The messages of one conversation, oldest first
+pub struct Transcript {
+    pub conversation: Recipient,
+    pub messages: Vec<MessageInformation>,
+}
+
+/// The conversations in memory, `only` picks one of them. Messages not loaded from the log are
+/// read from it, so nothing is left out.
+pub fn from_history(
+    conversations: &Conversations,
+    log: Option<&ChatLog>,
+    only: Option<&Recipient>,
+) -> io::Result<Vec<Transcript>> {
+    let mut transcripts = vec![];
+    for conversation in conversations.iter() {
+        if only.is_some_and(|only| *only != conversation.recipient) {
+            continue;
+        }
+        let mut messages = vec![];
+        if let (Some(log), 1..) = (log, conversation.unloaded) {
+            messages = log.read(&conversation.recipient)?;
+            messages.truncate(conversation.unloaded);
+        }
+        messages.extend(conversation.history.iter().filter_map(|entry| match entry {
+            HistoryEntry::Message { message, .. } => Some(message.clone()),
+            HistoryEntry::System(_) => None,
+        }));
+        transcripts.push(Transcript {
+            conversation: conversation.recipient.clone(),
+            messages,
+        });
+    }
+    Ok(transcripts)
+}
+
+/// Everything in a chat log, `only` picks one conversation
+pub fn from_log(log: &ChatLog, only: Option<&Recipient>) -> io::Result<Vec<Transcript>> {
+    let mut transcripts = vec![];
+    for conversation in log.conversations()? {
+        if only.is_some_and(|only| *only != conversation) {
+            continue;
+        }
+        transcripts.push(Transcript {
+            messages: log.read(&conversation)?,
+            conversation,
+        });
+    }
+    Ok(transcripts)
+}
+
+/// Writes the transcripts to `path`, `nickname` is who received the direct messages
+pub fn export_to_file(
+    format: Format,
+    transcripts: &[Transcript],
+    nickname: &str,
+    path: &Path,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write(format, transcripts, nickname, &mut out)?;
+    out.flush()
+}
+
+pub fn write(
+    format: Format,
+    transcripts: &[Transcript],
+    nickname: &str,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        Format::Markdown => markdown(transcripts, nickname, out),
+        Format::Html => html(transcripts, nickname, out),
+        Format::JsonLines => json_lines(transcripts, nickname, out),
+        Format::Text => text(transcripts, nickname, out),
+    }
+}
+
+fn title(conversation: &Recipient) -> String {
+    match conversation {
+        Recipient::Id(name) => format!("Direct messages with {name}"),
+        Recipient::All | Recipient::This => "Everyone".to_owned(),
+    }
+}
+
+/// Who a message went to, direct messages to us show our nickname
+fn recipient_name<'a>(recipient: &'a Recipient, nickname: &'a str) -> &'a str {
+    match recipient {
+        Recipient::All => "everyone",
+        Recipient::Id(name) => name,
+        Recipient::This => nickname,
+    }
+}
+
+fn local_time(time: u64) -> DateTime<Local> {
+    Local
+        .timestamp_opt(time as i64, 0)
+        .earliest()
+        .unwrap_or_default()
+}
+
+fn markdown(transcripts: &[Transcript], nickname: &str, out: &mut impl Write) -> io::Result<()> {
+    for (i, transcript) in transcripts.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(
+            out,
+            "## {}\n",
+            escape_markdown(&title(&transcript.conversation))
+        )?;
+        for message in &transcript.messages {
+            // A hard line break, then indented to stay in the list item
+            let text = escape_markdown(&message.message).replace('\n', "  \n  ");
+            writeln!(
+                out,
+                "- **{}** {} → {}: {}",
+                local_time(message.time).format("%Y-%m-%d %H:%M:%S"),
+                escape_markdown(&message.sender),
+                escape_markdown(recipient_name(&message.recipient, nickname)),
+                text
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Backslash escapes everything Markdown could take as formatting, control characters are
+/// replaced like in [`escape_text`]
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in escape_text(text).chars() {
+        if "\\`*_{}[]()<>#+-.!|~&".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn html(transcripts: &[Transcript], nickname: &str, out: &mut impl Write) -> io::Result<()> {
+    writeln!(
+        out,
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Chat transcript</title>
+<style>
+body {{ font-family: sans-serif; max-width: 60em; margin: auto; }}
+.message {{ margin: 0.3em 0; }}
+.message time {{ color: #888; }}
+.sender {{ font-weight: bold; }}
+.dm {{ color: #a0a; }}
+p {{ margin: 0 0 0 1em; white-space: pre-wrap; }}
+</style>
+</head>
+<body>"#
+    )?;
+    for transcript in transcripts {
+        writeln!(
+            out,
+            "<h2>{}</h2>",
+            escape_html(&title(&transcript.conversation))
+        )?;
+        for message in &transcript.messages {
+            let class = if message.is_all() {
+                "message"
+            } else {
+                "message dm"
+            };
+            let time = local_time(message.time);
+            writeln!(
+                out,
+                r#"<div class="{class}"><time datetime="{}">{}</time> <span class="sender">{}</span> → <span class="recipient">{}</span><p>{}</p></div>"#,
+                time.to_rfc3339(),
+                time.format("%Y-%m-%d %H:%M:%S"),
+                escape_html(&message.sender),
+                escape_html(recipient_name(&message.recipient, nickname)),
+                escape_html(&message.message)
+            )?;
+        }
+    }
+    writeln!(out, "</body>\n</html>")
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// One message of the JSON Lines export
+#[derive(Serialize)]
+struct JsonMessage<'a> {
+    conversation: Option<&'a str>,
+    /// Seconds since the Unix epoch
+    time: u64,
+    sender: &'a str,
+    /// `None` for messages to everyone
+    recipient: Option<&'a str>,
+    message: &'a str,
+}
+
+fn json_lines(transcripts: &[Transcript], nickname: &str, out: &mut impl Write) -> io::Result<()> {
+    for transcript in transcripts {
+        let conversation = match &transcript.conversation {
+            Recipient::Id(name) => Some(name.as_str()),
+            Recipient::All | Recipient::This => None,
+        };
+        for message in &transcript.messages {
+            let line = JsonMessage {
+                conversation,
+                time: message.time,
+                sender: &message.sender,
+                recipient: (!message.is_all())
+                    .then(|| recipient_name(&message.recipient, nickname)),
+                message: &message.message,
+            };
+            serde_json::to_writer(&mut *out, &line)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+fn text(transcripts: &[Transcript], nickname: &str, out: &mut impl Write) -> io::Result<()> {
+    for (i, transcript) in transcripts.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(
+            out,
+            "== {} ==",
+            escape_text(&title(&transcript.conversation))
+        )?;
+        for message in &transcript.messages {
+            // Continuation lines are indented, so they can't pass for a message of their own
+            let text = escape_text(&message.message).replace('\n', "\n    ");
+            writeln!(
+                out,
+                "[{}] {} -> {}: {}",
+                local_time(message.time).format("%Y-%m-%d %H:%M:%S"),
+                escape_text(&message.sender),
+                escape_text(recipient_name(&message.recipient, nickname)),
+                text
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces control characters other than newlines, so the text can't mess with a terminal
+fn escape_text(text: &str) -> String {
+    text.chars()
+        .filter(|c| *c != '\r')
+        .map(|c| {
+            if c.is_control() && c != '\n' {
+                char::REPLACEMENT_CHARACTER
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(time: u64, sender: &str, recipient: Recipient, text: &str) -> MessageInformation {
+        MessageInformation {
+            sender: sender.into(),
+            recipient,
+            message: text.into(),
+            time,
+        }
+    }
+
+    fn transcripts() -> Vec<Transcript> {
+        vec![
+            Transcript {
+                conversation: Recipient::All,
+                messages: vec![message(0, "bela", Recipient::All, "*hi* <b>&\"'\nsecond")],
+            },
+            Transcript {
+                conversation: Recipient::Id("cili".into()),
+                messages: vec![
+                    message(60, "cili", Recipient::This, "# not a header"),
+                    message(120, "anna", Recipient::Id("cili".into()), "bell\u{7}\r\nok"),
+                ],
+            },
+        ]
+    }
+
+    fn export(format: Format) -> String {
+        let mut out = vec![];
+        write(format, &transcripts(), "anna", &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn time(time: u64) -> String {
+        local_time(time).format("%Y-%m-%d %H:%M:%S").to_string()
+    }
+
+    #[test]
+    fn formats_parse() {
+        assert_eq!("MD".parse(), Ok(Format::Markdown));
+        assert_eq!("jsonl".parse(), Ok(Format::JsonLines));
+        assert!("pdf".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn markdown_escapes_formatting() {
+        let expected = format!(
+            "## Everyone\n\n\
+             - **{}** bela → everyone: \\*hi\\* \\<b\\>\\&\"'  \n  second\n\n\
+             ## Direct messages with cili\n\n\
+             - **{}** cili → anna: \\# not a header\n\
+             - **{}** anna → cili: bell\u{fffd}  \n  ok\n",
+            time(0),
+            time(60),
+            time(120)
+        );
+        assert_eq!(export(Format::Markdown), expected);
+    }
+
+    #[test]
+    fn html_escapes_markup() {
+        let html = export(Format::Html);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<p>*hi* &lt;b&gt;&amp;&quot;&#39;\nsecond</p>"));
+        assert!(html
+            .contains(r#"<span class="sender">cili</span> → <span class="recipient">anna</span>"#));
+        assert_eq!(html.matches(r#"<div class="message dm">"#).count(), 2);
+    }
+
+    #[test]
+    fn json_lines_round_trip() {
+        let lines: Vec<serde_json::Value> = export(Format::JsonLines)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            serde_json::json!({
+                "conversation": null,
+                "time": 0,
+                "sender": "bela",
+                "recipient": null,
+                "message": "*hi* <b>&\"'\nsecond",
+            })
+        );
+        assert_eq!(lines[1]["conversation"], "cili");
+        assert_eq!(lines[1]["recipient"], "anna");
+        assert_eq!(lines[2]["message"], "bell\u{7}\r\nok");
+    }
+
+    #[test]
+    fn text_indents_continuation_lines() {
+        let expected = format!(
+            "== Everyone ==\n\
+             [{}] bela -> everyone: *hi* <b>&\"'\n    second\n\n\
+             == Direct messages with cili ==\n\
+             [{}] cili -> anna: # not a header\n\
+             [{}] anna -> cili: bell\u{fffd}\n    ok\n",
+            time(0),
+            time(60),
+            time(120)
+        );
+        assert_eq!(export(Format::Text), expected);
+    }
+
+    #[test]
+    fn history_includes_unloaded_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = ChatLog::open(dir.path(), "server", "anna").unwrap();
+        for time in 0..3 {
+            log.append(
+                &Recipient::All,
+                &message(time, "bela", Recipient::All, &time.to_string()),
+            )
+            .unwrap();
+        }
+        let mut conversations = Conversations::default();
+        let messages = log.read(&Recipient::All).unwrap();
+        conversations.restore(&Recipient::All, messages.into_iter().skip(2), 2);
+        conversations.push(message(3, "cili", Recipient::This, "psst"), None, "anna");
+
+        let texts = |transcript: &Transcript| -> Vec<String> {
+            transcript
+                .messages
+                .iter()
+                .map(|message| message.message.clone())
+                .collect()
+        };
+        let all = from_history(&conversations, Some(&log), None).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(texts(&all[0]), ["0", "1", "2"]);
+        assert_eq!(texts(&all[1]), ["psst"]);
+
+        let cili = Recipient::Id("cili".into());
+        let one = from_history(&conversations, Some(&log), Some(&cili)).unwrap();
+        assert_eq!(one.len(), 1);
+        assert_eq!(one[0].conversation, cili);
+        assert_eq!(texts(&from_log(&log, None).unwrap()[0]), ["0", "1", "2"]);
+    }
+}
@@ -0,0 +1,153 @@
+use crossterm::event::{Event, KeyCode, KeyEventKind, MouseButton, MouseEventKind};
+use ratatui::layout::{Position, Rect};
+
+/// Something that can hold the focus.
+pub trait Focusable: Copy + PartialEq + 'static {
+    /// Everything that can be focused on the same screen as `self`, in tab order
+    fn ring(self) -> Vec<Self>;
+}
+
+/// Owns the focus and moves it around the ring of the current screen.
+pub struct FocusManager<T> {
+    current: T,
+    /// Where each focusable was drawn last, for focusing with the mouse
+    areas: Vec<(T, Rect)>,
+}
+
+impl<T: Focusable> FocusManager<T> {
+    pub fn new(current: T) -> Self {
+        Self {
+            current,
+            areas: vec![],
+        }
+    }
+
+    pub fn current(&self) -> T {
+        self.current
+    }
+
+    pub fn is_focused(&self, target: T) -> bool {
+        self.current == target
+    }
+
+    pub fn focus(&mut self, target: T) {
+        self.current = target;
+    }
+
+    pub fn next(&mut self) {
+        self.step(1);
+    }
+
+    pub fn previous(&mut self) {
+        self.step(-1);
+    }
+
+    fn step(&mut self, by: isize) {
+        let ring = self.current.ring();
+        let Some(index) = ring.iter().position(|item| *item == self.current) else {
+            return;
+        };
+        let len = ring.len() as isize;
+        self.current = ring[(index as isize + by).rem_euclid(len) as usize];
+    }
+
+    /// Forgets the areas of the last frame, call before registering the new ones
+    pub fn clear_areas(&mut self) {
+        self.areas.clear();
+    }
+
+    pub fn set_area(&mut self, target: T, area: Rect) {
+        self.areas.push((target, area));
+    }
+
+    /// Handles Tab, Shift-Tab and left clicks, returns whether the event was used up
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        match event {
+            Event::Key(key) if key.kind != KeyEventKind::Release => match key.code {
+                KeyCode::Tab => self.next(),
+                KeyCode::BackTab => self.previous(),
+                _ => return false,
+            },
+            Event::Mouse(mouse) if mouse.kind == MouseEventKind::Down(MouseButton::Left) => {
+                let position = Position::new(mouse.column, mouse.row);
+                let Some((target, _)) = self
+                    .areas
+                    .iter()
+                    .find(|(_, area)| area.contains(position))
+                else {
+                    return false;
+                };
+                self.current = *target;
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyEvent, KeyModifiers, MouseEvent};
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Field {
+        A,
+        B,
+        C,
+        Other,
+    }
+
+    impl Focusable for Field {
+        fn ring(self) -> Vec<Self> {
+            match self {
+                Field::Other => vec![Field::Other],
+                _ => vec![Field::A, Field::B, Field::C],
+            }
+        }
+    }
+
+    fn key(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn click(column: u16, row: u16) -> Event {
+        Event::Mouse(MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        })
+    }
+
+    #[test]
+    fn tab_cycles_the_ring() {
+        let mut focus = FocusManager::new(Field::A);
+        assert!(focus.handle_event(&key(KeyCode::Tab)));
+        assert_eq!(focus.current(), Field::B);
+        focus.next();
+        focus.next();
+        assert_eq!(focus.current(), Field::A);
+        assert!(focus.handle_event(&key(KeyCode::BackTab)));
+        assert_eq!(focus.current(), Field::C);
+        assert!(!focus.handle_event(&key(KeyCode::Char('x'))));
+
+        focus.focus(Field::Other);
+        focus.next();
+        assert_eq!(focus.current(), Field::Other);
+    }
+
+    #[test]
+    fn click_focuses_the_area_under_the_mouse() {
+        let mut focus = FocusManager::new(Field::A);
+        focus.set_area(Field::B, Rect::new(0, 0, 10, 5));
+        focus.set_area(Field::C, Rect::new(10, 0, 10, 5));
+        assert!(focus.handle_event(&click(12, 3)));
+        assert_eq!(focus.current(), Field::C);
+        assert!(!focus.handle_event(&click(12, 7)));
+        assert_eq!(focus.current(), Field::C);
+
+        focus.clear_areas();
+        assert!(!focus.handle_event(&click(2, 2)));
+    }
+}
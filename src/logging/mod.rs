@@ -0,0 +1,69 @@
+use chrono::Local;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Writes every record as a line with a timestamp, the level and the module it came from
+struct Logger {
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(
+            output,
+            "{} {:<5} {}: {}",
+            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        let _ = self
+            .output
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .flush();
+    }
+}
+
+/// `<state dir>/jedlikchat/jedlikchat.log`, the TUI can't log to the terminal it draws on
+pub fn default_path() -> Option<PathBuf> {
+    let dir = dirs::state_dir().or_else(dirs::data_dir)?;
+    Some(dir.join("jedlikchat").join("jedlikchat.log"))
+}
+
+pub fn to_file(level: LevelFilter, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    init(level, Box::new(file));
+    Ok(())
+}
+
+pub fn to_stderr(level: LevelFilter) {
+    init(level, Box::new(io::stderr()));
+}
+
+fn init(level: LevelFilter, output: Box<dyn Write + Send>) {
+    let logger = Logger {
+        output: Mutex::new(output),
+    };
+    // Only fails if there is a logger already, which can keep going
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -1,34 +1,84 @@
+mod chat_log;
+mod cli;
+mod commands;
+mod config;
+mod conversations;
+mod crash;
+mod export;
+mod focus;
+mod logging;
 mod networking;
-
-use crate::networking::Session;
+mod search;
+mod utils;
+mod widgets;
+use chat_log::{ChatLog, Retention};
+use clap::Parser;
+use cli::Cli;
+use config::{Config, KeyBinding, Theme};
+use conversations::{conversation_of, mentions, Conversations, HistoryEntry};
+use focus::{FocusManager, Focusable};
+use search::{Hit, Location, Query};
+use widgets::message_view::{MessageView, MessageViewState};
+use widgets::search_overlay::SearchOverlay;
+use widgets::text_field::TextField;
+use widgets::timestamp::{self as timestamps, TimestampFormat};
+use widgets::user_list::UserListState;
 
 mod application;
 use application::{ActiveEventLoop, Application, EventLoop, GeneralEvent};
 
-use std::cmp;
+use std::io::{self, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
-use crossterm::event::KeyCode;
-use networking::Event;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseEventKind};
+use networking::{protocol, DeliveryStatus, Event, MessageInformation, Recipient};
 use ratatui::layout::Flex;
 use ratatui::prelude::*;
 use ratatui::style::Style;
 use ratatui::widgets::{Block, Padding, Paragraph, Wrap};
-use ratatui::{DefaultTerminal, Frame};
-
-use tui_input::backend::crossterm::EventHandler;
-use tui_input::*;
+use ratatui::text::{Line, Span};
+use ratatui::Frame;
+
+fn main() -> Result<()> {
+    crash::install()?;
+    let cli = Cli::parse();
+    // Checked before the terminal is taken over, so the error can be read
+    let config = Config::load(cli.config.as_deref())
+        .and_then(|mut config| cli.apply(&mut config).map(|()| config))
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+
+    let connects = cli.connects();
+    if let Some(command) = cli.command {
+        logging::to_stderr(cli.log_level);
+        if let Err(e) = cli::run(command, &config) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(path) = logging::default_path() {
+        if let Err(e) = logging::to_file(cli.log_level, &path) {
+            eprintln!("Couldn't open the log file {}: {e}", path.display());
+        }
+    }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut event_loop = EventLoop::new();
-    let mut app = App::new();
+    let mut app = App::new(config);
+    app.connect_on_start = connects;
 
-    event_loop.run_app(&mut app).expect("Couldn't start app");
+    event_loop.run_app(&mut app).map_err(|e| eyre!("{e}"))?;
 
     Ok(())
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum ConnectingSelected {
     Name,
     Ip,
@@ -36,7 +86,7 @@ enum ConnectingSelected {
     Connect,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum ConnectedSelected {
     Messages,
     Users,
@@ -44,150 +94,426 @@ enum ConnectedSelected {
     Send,
 }
 
-#[derive(Clone, Copy)]
+enum ConnectionStatus {
+    Connected,
+    Disconnected(String),
+    Reconnecting { attempt: u32, delay: Duration },
+}
+
+impl ConnectionStatus {
+    fn line(&self) -> Line<'_> {
+        match self {
+            ConnectionStatus::Connected => Line::styled("connected", Color::Green),
+            ConnectionStatus::Disconnected(reason) => {
+                Line::styled(format!("disconnected: {reason}"), Color::Red)
+            }
+            ConnectionStatus::Reconnecting { attempt, delay } => Line::styled(
+                format!("reconnecting (attempt {attempt}, in {:.1}s)", delay.as_secs_f32()),
+                Color::Yellow,
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum AppState {
     ConnectingToNetwork(ConnectingSelected),
     Connected(ConnectedSelected),
 }
 
-pub struct InputWindow{
-    pub start: usize,
-    pub length: usize,
-}
-impl InputWindow{
-    #[inline]
-    pub fn empty() -> Self{
-       Self { start: 0, length: 0}
-    }
-
-    #[inline]
-    pub fn pruned_input<'a>(&'a self, source: &'a Input) -> &'a str{
-        if self.start + self.length > source.value().len() {
-            return &source.value()[self.start..];
+/// Gets the text field a focusable edits
+type FieldOf = fn(&mut App) -> &mut TextField;
+
+/// Everything focusable on the connect form in tab order, with the text field it edits
+const CONNECTING_FOCUS: &[(AppState, Option<FieldOf>)] = &[
+    (AppState::ConnectingToNetwork(ConnectingSelected::Name), Some(|app| &mut app.username)),
+    (AppState::ConnectingToNetwork(ConnectingSelected::Ip), Some(|app| &mut app.ip)),
+    (AppState::ConnectingToNetwork(ConnectingSelected::Port), Some(|app| &mut app.port)),
+    (AppState::ConnectingToNetwork(ConnectingSelected::Connect), None),
+];
+
+/// Everything focusable on the chat screen in tab order, with the text field it edits
+const CONNECTED_FOCUS: &[(AppState, Option<FieldOf>)] = &[
+    (AppState::Connected(ConnectedSelected::Messages), None),
+    (AppState::Connected(ConnectedSelected::Users), None),
+    (AppState::Connected(ConnectedSelected::Recipient), Some(|app| &mut app.recipient)),
+    (AppState::Connected(ConnectedSelected::Send), Some(|app| &mut app.message)),
+];
+
+impl AppState {
+    fn screen(self) -> &'static [(AppState, Option<FieldOf>)] {
+        match self {
+            AppState::ConnectingToNetwork(_) => CONNECTING_FOCUS,
+            AppState::Connected(_) => CONNECTED_FOCUS,
         }
-        &source.value()[self.start..(self.start + self.length)]
     }
+}
 
-    #[inline]
-    pub fn cursor_changed(&mut self, new_cursor: usize) {
-        self.start = cmp::min(self.start, new_cursor); 
-        if new_cursor > self.start + self.length{
-            self.start = new_cursor - self.length;
-        }
+impl Focusable for AppState {
+    fn ring(self) -> Vec<Self> {
+        self.screen().iter().map(|(state, _)| *state).collect()
     }
 }
 
 struct App {
-    messages: Vec<String>,
+    conversations: Conversations,
     users: Vec<String>,
-    session: Option<Session>,
-    terminal: Option<DefaultTerminal>,
-    state: AppState,
+    user_list: UserListState,
+    connection: ConnectionStatus,
+    connect_error: Option<String>,
+    /// The nickname of the session being connected, until it's done
+    connecting: Option<String>,
+    /// Set when the server came from the command line, connects without the form
+    connect_on_start: bool,
+    /// Address of the current session, kept after losing the connection until disconnecting
+    server: Option<SocketAddr>,
+    commands: commands::Registry,
+    /// Where the chat logs go, `None` if there is no data directory
+    log_root: Option<PathBuf>,
+    log: Option<ChatLog>,
+    retention: Retention,
+    timestamps: TimestampFormat,
+    config: Config,
+    /// Open while searching, takes all input
+    search: Option<SearchOverlay>,
+    focus: FocusManager<AppState>,
+
+    username: TextField,
+    ip: TextField,
+    port: TextField,
+    message: TextField,
+    recipient: TextField,
+}
 
-    username_input: Input,
-    username_window: InputWindow,
+impl App {
+    #[inline]
+    fn new(config: Config) -> Self {
+        let mut app = Self {
+            conversations: Conversations::default(),
+            connection: ConnectionStatus::Disconnected("not connected".into()),
+            connect_error: None,
+            connecting: None,
+            connect_on_start: false,
+            server: None,
+            commands: commands::Registry::with_builtins(),
+            log_root: ChatLog::default_root(),
+            log: None,
+            retention: config.log.retention(),
+            timestamps: config.timestamp_format,
+            search: None,
+            users: vec![],
+            user_list: UserListState::default(),
+            focus: FocusManager::new(AppState::ConnectingToNetwork(ConnectingSelected::Name)),
+            username: TextField::default(),
+            ip: TextField::default(),
+            port: TextField::default(),
+            message: TextField::default(),
+            recipient: TextField::default(),
+            config,
+        };
+        app.fill_connect_form();
+        app
+    }
 
-    ip_input: Input,
-    ip_window: InputWindow,
+    /// Puts the nickname and the default server from the config in the connect form, focusing
+    /// the connect button if nothing is missing
+    fn fill_connect_form(&mut self) {
+        if let Some(nickname) = self.config.default_nickname() {
+            self.username.set_value(nickname);
+        }
+        if let Some(profile) = self.config.default_profile() {
+            self.ip.set_value(&profile.host);
+            self.port.set_value(&profile.port.to_string());
+            if !self.username.value().is_empty() {
+                self.focus.focus(AppState::ConnectingToNetwork(ConnectingSelected::Connect));
+            }
+        }
+    }
 
-    port_input: Input,
-    port_window: InputWindow,
+    /// Checks the form and starts connecting, [`App::connected`] gets the result
+    fn connect(&mut self, event_loop: &mut ActiveEventLoop) {
+        let result = check_connect_form(
+            self.username.value(),
+            self.ip.value(),
+            self.port.value(),
+        )
+        .and_then(|(name, host, port)| {
+            self.start_session(event_loop, &name, move || resolve(&host, port))
+        });
+        match result {
+            Ok(()) => self.connect_error = None,
+            Err(e) => self.connect_error = Some(e),
+        }
+    }
 
-    message_input: Input,
-    message_window: InputWindow,
+    /// Starts connecting a session that replaces the current one once it's connected,
+    /// `resolve` runs on the connecting thread
+    fn start_session(
+        &mut self,
+        event_loop: &mut ActiveEventLoop,
+        name: &str,
+        resolve: impl FnOnce() -> Result<SocketAddr, String> + Send + 'static,
+    ) -> Result<(), String> {
+        if self.connecting.is_some() {
+            return Err("Already connecting".to_owned());
+        }
+        event_loop
+            .start_network_session(name, self.config.reconnect.policy(), resolve)
+            .map_err(|e| e.to_string())?;
+        self.connecting = Some(name.to_owned());
+        Ok(())
+    }
 
-    recipient_input: Input,
-    recipient_window: InputWindow,
-}
+    /// The session started by [`App::start_session`] is in place, or it couldn't connect
+    fn connected(&mut self, result: Result<SocketAddr, String>) {
+        let Some(name) = self.connecting.take() else {
+            return;
+        };
+        let on_form = matches!(self.focus.current(), AppState::ConnectingToNetwork(_));
+        match result {
+            Ok(address) => {
+                self.connect_error = None;
+                self.server = Some(address);
+                self.users.clear();
+                self.open_log(&name, address);
+                if on_form {
+                    self.focus.focus(AppState::Connected(ConnectedSelected::Send));
+                }
+            }
+            Err(e) if on_form => self.connect_error = Some(e),
+            Err(e) => self.system_line(e),
+        }
+    }
 
-impl App {
-    #[inline]
-    fn new() -> Self {
-        Self {
-            messages: vec![],
-            session: None,
-            users: vec![],
-            terminal: None,
-            state: AppState::Connected(ConnectedSelected::Send),
-            username_input: "".into(),
-            username_window: InputWindow::empty(),
-            ip_input: "".into(),
-            ip_window: InputWindow::empty(),
-            port_input: "".into(),
-            port_window: InputWindow::empty(),
-            message_input: "".into(),
-            message_window: InputWindow::empty(),
-            recipient_input: "".into(),
-            recipient_window: InputWindow::empty(),
+    /// Switches to the log of the server and nickname, restoring its recent messages unless
+    /// it's the one already in use
+    fn open_log(&mut self, name: &str, address: SocketAddr) {
+        let Some(root) = &self.log_root else {
+            return;
+        };
+        if !self.retention.enabled {
+            return;
+        }
+        let log = match ChatLog::open(root, &address.to_string(), name) {
+            Ok(log) => log,
+            Err(e) => {
+                self.log = None;
+                self.system_line(format!("Couldn't open the chat log: {e}"));
+                return;
+            }
+        };
+        if self.log.as_ref() == Some(&log) {
+            return;
+        }
+        let restored = log
+            .apply_retention(&self.retention, SystemTime::now())
+            .and_then(|()| self.restore_history(&log));
+        if let Err(e) = restored {
+            self.system_line(format!("Couldn't restore the chat log: {e}"));
         }
+        self.log = Some(log);
     }
 
-    #[inline]
-    fn get_current_input_mut(&mut self) -> Option<&mut Input> {
-        match self.state {
-            AppState::Connected(selected) => match selected {
-                ConnectedSelected::Send => Some(&mut self.message_input),
-                ConnectedSelected::Recipient => Some(&mut self.recipient_input),
-                _ => None,
-            },
-            AppState::ConnectingToNetwork(selected) => match selected {
-                ConnectingSelected::Name => Some(&mut self.username_input),
-                ConnectingSelected::Ip => Some(&mut self.ip_input),
-                ConnectingSelected::Port => Some(&mut self.port_input),
-                _ => None,
-            },
+    fn restore_history(&mut self, log: &ChatLog) -> io::Result<()> {
+        self.conversations = Conversations::default();
+        self.recipient_follows_tab();
+        for conversation in log.conversations()? {
+            let mut messages = log.read(&conversation)?;
+            let unloaded = messages.len().saturating_sub(self.retention.restore);
+            self.conversations
+                .restore(&conversation, messages.drain(unloaded..), unloaded);
         }
+        Ok(())
     }
 
-    #[inline]
-    fn get_current_input(&self) -> Option<&Input> {
-        match self.state {
-            AppState::Connected(selected) => match selected {
-                ConnectedSelected::Send => Some(&self.message_input),
-                ConnectedSelected::Recipient => Some(&self.recipient_input),
-                _ => None,
-            },
-            AppState::ConnectingToNetwork(selected) => match selected {
-                ConnectingSelected::Name => Some(&self.username_input),
-                ConnectingSelected::Ip => Some(&self.ip_input),
-                ConnectingSelected::Port => Some(&self.port_input),
-                _ => None,
-            },
+    fn log_message(&mut self, message: &MessageInformation) {
+        let Some(log) = &self.log else {
+            return;
+        };
+        if let Err(e) = log.append(&conversation_of(message), message) {
+            // One error is enough, it would most likely fail the same way for every message
+            log::error!("couldn't write the chat log: {e}");
+            self.log = None;
+            self.system_line(format!("Stopped logging, couldn't write the chat log: {e}"));
         }
     }
-    #[inline]
-    fn get_current_input_window_mut(&mut self) -> Option<&mut InputWindow> {
-        match self.state {
-            AppState::Connected(selected) => match selected {
-                ConnectedSelected::Send => Some(&mut self.message_window),
-                ConnectedSelected::Recipient => Some(&mut self.recipient_window),
-                _ => None,
-            },
-            AppState::ConnectingToNetwork(selected) => match selected {
-                ConnectingSelected::Name => Some(&mut self.username_window),
-                ConnectingSelected::Ip => Some(&mut self.ip_window),
-                ConnectingSelected::Port => Some(&mut self.port_window),
-                _ => None,
-            },
+
+    /// Rings the terminal bell for the received messages the config asks for
+    fn notify(&self, message: &MessageInformation) {
+        let notifications = &self.config.notifications;
+        let nickname = self.username.value();
+        // Broadcasts aren't echoed back, but a direct message to our own nickname is delivered
+        // to us, what we sent ourselves shouldn't ring
+        let ring = if message.sender == nickname {
+            false
+        } else if message.is_all() {
+            notifications.mentions && mentions(&message.message, nickname)
+        } else {
+            notifications.direct_messages
+        };
+        if ring {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(b"\x07").and_then(|()| stdout.flush());
         }
     }
 
-    #[inline]
-    fn get_current_input_window(&self) -> Option<&InputWindow> {
-        match self.state {
-            AppState::Connected(selected) => match selected {
-                ConnectedSelected::Send => Some(&self.message_window),
-                ConnectedSelected::Recipient => Some(&self.recipient_window),
-                _ => None,
-            },
-            AppState::ConnectingToNetwork(selected) => match selected {
-                ConnectingSelected::Name => Some(&self.username_window),
-                ConnectingSelected::Ip => Some(&self.ip_window),
-                ConnectingSelected::Port => Some(&self.port_window),
-                _ => None,
+    fn system_line(&mut self, line: String) {
+        self.conversations.push_system(line);
+    }
+
+    fn clear_history(&mut self) {
+        let conversation = self.conversations.active_mut();
+        conversation.history.clear();
+        conversation.view = MessageViewState::default();
+    }
+
+    /// Switches to the conversation with `recipient`, opening it if needed
+    fn select_conversation(&mut self, recipient: &Recipient) {
+        self.conversations.select_recipient(recipient);
+        self.recipient_follows_tab();
+    }
+
+    /// Puts the other party of the active conversation in the recipient field, an empty field
+    /// means everyone
+    fn recipient_follows_tab(&mut self) {
+        let name = match &self.conversations.active().recipient {
+            Recipient::Id(name) => name.clone(),
+            Recipient::All | Recipient::This => String::new(),
+        };
+        self.recipient.set_value(&name);
+    }
+
+    fn send_message(&mut self, event_loop: &mut ActiveEventLoop) {
+        let message = self.message.value().to_owned();
+        if message.is_empty() {
+            return;
+        }
+        if commands::is_command(&message) {
+            let result = self
+                .commands
+                .parse(&message)
+                .and_then(|(run, args)| run(self, event_loop, args));
+            match result {
+                Ok(()) => self.message.reset(),
+                Err(e) => self.system_line(e),
+            }
+            return;
+        }
+        // What's left starting with a slash was escaped as `//`
+        let message = message.strip_prefix('/').unwrap_or(&message);
+        let recipient = match self.recipient.value() {
+            "" => Recipient::All,
+            name => Recipient::Id(name.to_owned()),
+        };
+        // Keep the text if it didn't go out so it can be sent again
+        if event_loop.send_message(recipient, message).is_ok() {
+            self.message.reset();
+        }
+    }
+
+    fn open_search(&mut self, query: &str) {
+        let unloaded = match &self.log {
+            Some(log) => search::read_unloaded(&self.conversations, log)
+                .map_err(|e| format!("Couldn't read the chat log: {e}")),
+            None => Ok(vec![]),
+        };
+        self.search = Some(SearchOverlay::new(query, unloaded));
+        self.run_search();
+    }
+
+    fn run_search(&mut self) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let query = search.input.value();
+        let results = if query.trim().is_empty() {
+            Ok(vec![])
+        } else {
+            Query::parse(query).and_then(|query| {
+                let unloaded = search.unloaded()?;
+                Ok(search::search(&query, &self.conversations, unloaded))
+            })
+        };
+        search.set_results(results);
+    }
+
+    fn handle_search_input(&mut self, event: &crossterm::event::Event) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        match event {
+            crossterm::event::Event::Key(key) => match key.code {
+                KeyCode::Esc => {
+                    self.search = None;
+                    return;
+                }
+                KeyCode::Enter => {
+                    if let Some(hit) = search.selected().cloned() {
+                        self.search = None;
+                        self.jump_to(&hit);
+                    }
+                    return;
+                }
+                KeyCode::Up => {
+                    search.select_previous();
+                    return;
+                }
+                KeyCode::Down => {
+                    search.select_next();
+                    return;
+                }
+                _ => {}
             },
+            crossterm::event::Event::Mouse(_) => return,
+            _ => {}
+        }
+        let query = search.input.value().to_owned();
+        search.input.handle_event(event);
+        if search.input.value() != query {
+            self.run_search();
         }
     }
+
+    /// Shows the message of a search hit at the top of its conversation
+    fn jump_to(&mut self, hit: &Hit) {
+        let index = match hit.location {
+            Location::History(index) => index,
+            Location::Log(index) => {
+                if let Err(e) = self.load_older(&hit.conversation, index) {
+                    self.system_line(format!("Couldn't read the chat log: {e}"));
+                    return;
+                }
+                0
+            }
+        };
+        self.select_conversation(&hit.conversation);
+        self.conversations.active_mut().view.show_message(index);
+        self.focus.focus(AppState::Connected(ConnectedSelected::Messages));
+    }
+
+    /// Loads the messages of a conversation that are still only in the chat log, starting at
+    /// index `from` of the log
+    fn load_older(&mut self, conversation: &Recipient, from: usize) -> io::Result<()> {
+        let Some(log) = &self.log else {
+            return Ok(());
+        };
+        let unloaded = self
+            .conversations
+            .iter()
+            .find(|loaded| loaded.recipient == *conversation)
+            .map_or(0, |loaded| loaded.unloaded);
+        let mut records = log.read(conversation)?;
+        records.truncate(unloaded);
+        self.conversations
+            .restore(conversation, records.drain(from.min(unloaded)..), from.min(unloaded));
+        Ok(())
+    }
+
+    /// The text field behind a focusable, the one place that ties the two together
+    fn text_field_mut(&mut self, target: AppState) -> Option<&mut TextField> {
+        let (_, field) = target.screen().iter().find(|(state, _)| *state == target)?;
+        field.map(|field| field(self))
+    }
 }
 
 impl Application for App {
@@ -196,30 +522,160 @@ impl Application for App {
 
         match event {
             GeneralEvent::Input(event) => {
-                match &event {
-                    crossterm::event::Event::Key(key) => {
-                        if key.code == KeyCode::Esc {
-                            event_loop.exit();
+                if self.search.is_some() {
+                    self.handle_search_input(&event);
+                    return;
+                }
+                if let crossterm::event::Event::Key(key) = &event {
+                    if pressed(&self.config.keybindings.quit, key) {
+                        event_loop.exit();
+                    }
+                }
+                if self.focus.handle_event(&event) {
+                    return;
+                }
+                if let crossterm::event::Event::Key(key) = &event {
+                    if let AppState::Connected(_) = self.focus.current() {
+                        let keys = &self.config.keybindings;
+                        if pressed(&keys.search, key) {
+                            self.open_search("");
+                            return;
+                        }
+                        let switched = match (key.code, key.modifiers) {
+                            (KeyCode::Char(c @ '1'..='9'), KeyModifiers::ALT) => {
+                                self.conversations.select(c as usize - '1' as usize)
+                            }
+                            _ if pressed(&keys.previous_tab, key) => {
+                                self.conversations.select_previous();
+                                true
+                            }
+                            _ if pressed(&keys.next_tab, key) => {
+                                self.conversations.select_next();
+                                true
+                            }
+                            _ => false,
+                        };
+                        if switched {
+                            self.recipient_follows_tab();
+                            return;
+                        }
+                    }
+                    match (self.focus.current(), key.code) {
+                        (AppState::Connected(ConnectedSelected::Send), _)
+                            if pressed(&self.config.keybindings.newline, key) =>
+                        {
+                            self.message.insert_newline();
+                            return;
+                        }
+                        (AppState::Connected(ConnectedSelected::Send), KeyCode::Enter) => {
+                            self.send_message(event_loop);
+                            return;
+                        }
+                        (AppState::Connected(ConnectedSelected::Send), KeyCode::Up) => {
+                            self.message.move_up();
+                            return;
+                        }
+                        (AppState::Connected(ConnectedSelected::Send), KeyCode::Down) => {
+                            self.message.move_down();
+                            return;
                         }
+                        (
+                            AppState::ConnectingToNetwork(ConnectingSelected::Connect),
+                            KeyCode::Enter,
+                        ) => {
+                            self.connect(event_loop);
+                            return;
+                        }
+                        (AppState::ConnectingToNetwork(_), KeyCode::Down | KeyCode::Enter) => {
+                            self.focus.next();
+                            return;
+                        }
+                        (AppState::ConnectingToNetwork(_), KeyCode::Up) => {
+                            self.focus.previous();
+                            return;
+                        }
+                        (AppState::Connected(ConnectedSelected::Messages), code) => {
+                            let view = &mut self.conversations.active_mut().view;
+                            match code {
+                                KeyCode::Up => view.scroll_up(1),
+                                KeyCode::Down => view.scroll_down(1),
+                                KeyCode::PageUp => view.page_up(),
+                                KeyCode::PageDown => view.page_down(),
+                                KeyCode::Home => view.scroll_to_top(),
+                                KeyCode::End => view.scroll_to_bottom(),
+                                _ => {}
+                            }
+                            return;
+                        }
+                        (AppState::Connected(ConnectedSelected::Users), code) => {
+                            match code {
+                                KeyCode::Up => self.user_list.select_previous(&self.users),
+                                KeyCode::Down => self.user_list.select_next(&self.users),
+                                KeyCode::Char(c) => self.user_list.push_filter(c),
+                                KeyCode::Backspace => self.user_list.pop_filter(),
+                                KeyCode::Enter => {
+                                    if let Some(entry) = self.user_list.selected(&self.users) {
+                                        self.select_conversation(&entry.recipient());
+                                        self.user_list.clear_filter();
+                                        self.focus
+                                            .focus(AppState::Connected(ConnectedSelected::Send));
+                                    }
+                                }
+                                _ => {}
+                            }
+                            return;
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
-                if let Some(input_field) = self.get_current_input_mut() {
-                    input_field.handle_event(&event);
-                    let cursor = input_field.cursor();
-                    self.get_current_input_window_mut().unwrap().cursor_changed(cursor);
+                if let crossterm::event::Event::Mouse(mouse) = &event {
+                    if self.focus.is_focused(AppState::Connected(ConnectedSelected::Messages)) {
+                        let view = &mut self.conversations.active_mut().view;
+                        match mouse.kind {
+                            MouseEventKind::ScrollUp => view.scroll_up(3),
+                            MouseEventKind::ScrollDown => view.scroll_down(3),
+                            _ => {}
+                        }
+                    }
+                    return;
+                }
+                if let Some(field) = self.text_field_mut(self.focus.current()) {
+                    field.handle_event(&event);
                 }
             }
 
             GeneralEvent::Networking(event) => match event {
                 Event::UsersList(users) => self.users = users,
                 Event::MessageReceived(message) => {
-                    self.messages
-                        .push(format!("{}: {}", message.sender, message.message));
+                    self.notify(&message);
+                    self.log_message(&message);
+                    self.conversations
+                        .push(message, None, self.username.value());
+                }
+                Event::MessageSent { id, message } => {
+                    self.log_message(&message);
+                    let conversation = self.conversations.push(
+                        message,
+                        Some((id, DeliveryStatus::Pending)),
+                        self.username.value(),
+                    );
+                    // Sending something means wanting to see it
+                    conversation.view.scroll_to_bottom();
+                }
+                Event::Delivery { id, status } => self.conversations.set_delivery(id, status),
+                Event::Connected => self.connection = ConnectionStatus::Connected,
+                Event::Disconnected { reason } => {
+                    self.connection = ConnectionStatus::Disconnected(reason)
                 }
-                _ => event_loop.exit(),
+                Event::Reconnecting { attempt, delay } => {
+                    self.connection = ConnectionStatus::Reconnecting { attempt, delay }
+                }
+                // Only comes after the session was stopped, which already updated the status
+                Event::Quit => {}
             },
 
+            GeneralEvent::SessionStarted(result) => self.connected(result),
+
             _ => {
                 event_loop.exit();
             }
@@ -227,29 +683,34 @@ impl Application for App {
     }
 
     fn init(&mut self, event_loop: &mut ActiveEventLoop) {
+        if self.connect_on_start {
+            // Errors stay on the connect form like after pressing the button
+            self.connect(event_loop);
+        }
         event_loop.request_redraw();
     }
 
     fn render(&mut self, frame: &mut Frame) {
-        let selected = Style::new().fg(ratatui::style::Color::LightGreen);
-        let unselected = Style::new().fg(ratatui::style::Color::Green);
-        let selected_block_rect: Option<Rect>;
-
-        let mut selected_input_rect = None;
-        match self.state {
-            AppState::ConnectingToNetwork(select) => {
+        let theme = &self.config.theme;
+        let selected = Style::new().fg(theme.accent);
+        let unselected = Style::new().fg(theme.border);
+        let focused = self.focus.current();
+        let style = |target| if focused == target { selected } else { unselected };
+
+        // Text fields and the areas they are drawn in, the focused one gets the cursor
+        let mut field_rects = vec![];
+        self.focus.clear_areas();
+        match focused {
+            AppState::ConnectingToNetwork(_) => {
                 let block = Block::bordered().padding(Padding::horizontal(1));
 
                 let centered_area = center(frame.area(), 50, 50);
 
-                let mut name_block = Block::bordered().title("Username").style(unselected);
-                let mut ip_block = Block::bordered().title("IP").style(unselected);
-                let mut port_block = Block::bordered().title("Port").style(unselected);
-
-                let [name_area, ip_area, lower_area] = Layout::vertical([
+                let [name_area, ip_area, lower_area, error_area] = Layout::vertical([
                     Constraint::Percentage(33),
                     Constraint::Percentage(33),
                     Constraint::Percentage(33),
+                    Constraint::Length(2),
                 ])
                 .margin(1)
                 .areas(centered_area);
@@ -261,55 +722,40 @@ impl Application for App {
                 ])
                 .areas(lower_area);
 
-                let mut connect_block = Block::bordered().title("ConnectPlaceholder");
-
-                match select {
-                    ConnectingSelected::Name => {
-                        selected_block_rect = Some(name_block.inner(name_area));
-                        selected_input_rect = selected_block_rect;
-                        name_block = name_block.style(selected)
-                    }
-                    ConnectingSelected::Ip => {
-                        selected_block_rect = Some(ip_block.inner(ip_area));
-                        selected_input_rect = selected_block_rect;
-                        ip_block = ip_block.style(selected)
-                    }
-                    ConnectingSelected::Port => {
-                        selected_block_rect = Some(port_block.inner(port_area));
-                        selected_input_rect = selected_block_rect;
-                        port_block = port_block.style(selected)
-                    }
-                    ConnectingSelected::Connect => {
-                        selected_block_rect = None;
-                        connect_block = connect_block.style(selected)
-                    }
-                }
-
-                let name_rect = name_block.inner(name_area);
-                let ip_rect = ip_block.inner(ip_area);
-                let port_rect = port_block.inner(port_area);
-                
-                self.username_window.length = (name_rect.x * name_rect.y) as usize;
-                self.ip_window.length = (ip_rect.x * ip_rect.y) as usize;
-                self.port_window.length = (port_rect.x *port_rect.y) as usize;
-
-                let username_text = Paragraph::new(self.username_window.pruned_input(&self.username_input)).wrap(Wrap{ trim: false});
-                let ip_text = Paragraph::new(self.ip_window.pruned_input(&self.ip_input)).wrap(Wrap{ trim: false});
-                let port_text = Paragraph::new(self.port_window.pruned_input(&self.port_input)).wrap(Wrap{ trim: false});
-
-                frame.render_widget(name_block, name_area);
-                frame.render_widget(ip_block, ip_area);
-                frame.render_widget(port_block, port_area);
-                frame.render_widget(connect_block, connect_area);
                 frame.render_widget(block, centered_area);
+                for (target, title, area) in [
+                    (ConnectingSelected::Name, "Username", name_area),
+                    (ConnectingSelected::Ip, "IP", ip_area),
+                    (ConnectingSelected::Port, "Port", port_area),
+                ] {
+                    let target = AppState::ConnectingToNetwork(target);
+                    let field_block = Block::bordered().title(title).style(style(target));
+                    field_rects.push((target, field_block.inner(area)));
+                    frame.render_widget(field_block, area);
+                    self.focus.set_area(target, area);
+                }
 
-                frame.render_widget(username_text, name_rect);
-                frame.render_widget(ip_text, ip_area);
-                frame.render_widget(port_text, port_area);
-
-
+                let connect = AppState::ConnectingToNetwork(ConnectingSelected::Connect);
+                let mut connect_block = Block::bordered();
+                if focused == connect {
+                    connect_block = connect_block.style(selected);
+                }
+                let label = if self.connecting.is_some() { "Connecting..." } else { "Connect" };
+                frame.render_widget(
+                    Paragraph::new(label).centered().block(connect_block),
+                    connect_area,
+                );
+                self.focus.set_area(connect, connect_area);
+                if let Some(error) = &self.connect_error {
+                    frame.render_widget(
+                        Paragraph::new(error.as_str())
+                            .style(Color::Red)
+                            .wrap(Wrap { trim: true }),
+                        error_area,
+                    );
+                }
             }
-            AppState::Connected(select) => {
+            AppState::Connected(_) => {
                 let [left_area, users_area] =
                     Layout::horizontal([Constraint::Percentage(100), Constraint::Percentage(20)])
                         .areas(frame.area());
@@ -320,57 +766,170 @@ impl Application for App {
                     Layout::horizontal([Constraint::Percentage(20), Constraint::Percentage(100)])
                         .areas(sending_area);
 
-                let mut message_block = Block::bordered().title("Messages");
-                let mut users_block = Block::bordered().title("Users");
-                let mut recipient_block = Block::bordered().title("Recipient");
-                let mut message_send_block = Block::bordered().title("Send");
+                let messages_target = AppState::Connected(ConnectedSelected::Messages);
+                let users_target = AppState::Connected(ConnectedSelected::Users);
+                let mut message_block = Block::bordered()
+                    .title("Messages")
+                    .title(self.connection.line().right_aligned());
+                if focused == messages_target {
+                    message_block = message_block.style(selected);
+                }
+                let mut users_block = Block::bordered();
+                if focused == users_target {
+                    users_block = users_block.style(selected);
+                }
 
-                match select {
-                    ConnectedSelected::Messages => {
-                        selected_block_rect = Some(message_block.inner(message_area));
-                        message_block = message_block.style(selected)
-                    }
-                    ConnectedSelected::Users => {
-                        selected_block_rect = Some(users_block.inner(users_area));
-                        users_block = users_block.style(selected)
-                    }
-                    ConnectedSelected::Recipient => {
-                        selected_block_rect = Some(recipient_block.inner(recipient_area));
-                        selected_input_rect = selected_block_rect;
-                        recipient_block = recipient_block.style(selected)
-                    }
-                    ConnectedSelected::Send => {
-                        selected_block_rect = Some(message_send_block.inner(message_send_area));
-                        selected_input_rect = selected_block_rect;
-                        message_send_block = message_send_block.style(selected)
+                let [tabs_area, history_area] =
+                    Layout::vertical([Constraint::Length(1), Constraint::Fill(1)])
+                        .areas(message_block.inner(message_area));
+                frame.render_widget(self.conversations.tabs(theme), tabs_area);
+                let conversation = self.conversations.active_mut();
+                let messages = &conversation.history;
+                let (format, now) = (self.timestamps, networking::system_clock());
+                let history = MessageView::new(messages.len(), |i| {
+                    message_line(messages, i, format, now, theme)
+                });
+                frame.render_stateful_widget(history, history_area, &mut conversation.view);
+                frame.render_widget(message_block, message_area);
+                self.focus.set_area(messages_target, message_area);
+
+                let users = self.user_list.widget(&self.users, users_block, theme.accent);
+                frame.render_stateful_widget(users, users_area, self.user_list.list_state());
+                self.focus.set_area(users_target, users_area);
+
+                for (target, title, area) in [
+                    (ConnectedSelected::Recipient, "Recipient", recipient_area),
+                    (ConnectedSelected::Send, "Send", message_send_area),
+                ] {
+                    let target = AppState::Connected(target);
+                    let mut field_block = Block::bordered().title(title);
+                    if focused == target {
+                        field_block = field_block.style(selected);
                     }
+                    field_rects.push((target, field_block.inner(area)));
+                    frame.render_widget(field_block, area);
+                    self.focus.set_area(target, area);
                 }
-                let send_rect = message_send_block.inner(message_send_area);
-                let recipient_rect = recipient_block.inner(recipient_area);
-                
-                self.message_window.length = (send_rect.x * send_rect.y) as usize;
-                self.recipient_window.length = (recipient_rect.x *recipient_rect.y) as usize;
+            }
+        }
+        for (target, rect) in field_rects {
+            let Some(field) = self.text_field_mut(target) else {
+                continue;
+            };
+            frame.render_widget(field.widget(rect), rect);
+            if target == focused {
+                frame.set_cursor_position(field.cursor_position(rect));
+            }
+        }
+        if let Some(search) = &mut self.search {
+            let area = frame.area();
+            search.render(frame, area, &self.config.theme);
+        }
+    }
+}
+fn pressed(bindings: &[KeyBinding], key: &KeyEvent) -> bool {
+    bindings.iter().any(|binding| binding.matches(key))
+}
 
-                let message_text = Paragraph::new(self.message_window.pruned_input(&self.message_input)).wrap(Wrap{ trim: false});
-                let recipient_text = Paragraph::new(self.recipient_window.pruned_input(&self.recipient_input)).wrap(Wrap{ trim: false});
+/// Checks the connect form, returns the nickname and the resolved server address
+fn validate_connect_form(
+    name: &str,
+    host: &str,
+    port: &str,
+) -> Result<(String, SocketAddr), String> {
+    let (name, host, port) = check_connect_form(name, host, port)?;
+    Ok((name, resolve(&host, port)?))
+}
 
-                frame.render_widget(message_block, message_area);
-                frame.render_widget(users_block, users_area);
-                frame.render_widget(&recipient_block, recipient_area);
-                frame.render_widget(&message_send_block, message_send_area);
+/// Checks the connect form without resolving the host, returns the nickname, host and port
+fn check_connect_form(
+    name: &str,
+    host: &str,
+    port: &str,
+) -> Result<(String, String, u16), String> {
+    if name.is_empty() {
+        return Err("Username can't be empty".to_owned());
+    }
+    if !protocol::is_valid_nickname(name) {
+        return Err("Username can't contain ':' or ','".to_owned());
+    }
+    let host = host.trim();
+    if host.is_empty() {
+        return Err("IP can't be empty".to_owned());
+    }
+    let port: u16 = port
+        .trim()
+        .parse()
+        .map_err(|_| "Port has to be a number between 0 and 65535".to_owned())?;
+    Ok((name.to_owned(), host.to_owned(), port))
+}
 
-                frame.render_widget(message_text, send_rect);
-                frame.render_widget(recipient_text, recipient_rect);
-            }
+/// Blocks until the lookup is done, which can take a while
+fn resolve(host: &str, port: u16) -> Result<SocketAddr, String> {
+    (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Couldn't resolve {host}: {e}"))?
+        .next()
+        .ok_or_else(|| format!("No address found for {host}"))
+}
+
+/// The line of the `index`th entry of a history, starting with a day separator if it's the first
+/// message of a day
+fn message_line<'a>(
+    history: &'a [HistoryEntry],
+    index: usize,
+    timestamps: TimestampFormat,
+    now: u64,
+    theme: &Theme,
+) -> Line<'a> {
+    let (message, delivery) = match &history[index] {
+        HistoryEntry::Message {
+            message, delivery, ..
+        } => (message, delivery),
+        HistoryEntry::System(line) => {
+            return Line::styled(format!("* {line}"), Style::new().fg(theme.system).italic())
         }
-        if let Some(rect) = selected_input_rect {
-            let current_input_cursor = (self.get_current_input().unwrap().cursor() - self.get_current_input_window().unwrap().start)as u16;
-            let x = current_input_cursor % rect.width + rect.x;
-            let y = current_input_cursor / rect.width + rect.y;
-            frame.set_cursor_position((x, y));
+    };
+    let mut spans = vec![];
+    let previous = history[..index].iter().rev().find_map(|entry| match entry {
+        HistoryEntry::Message { message, .. } => Some(message.time),
+        HistoryEntry::System(_) => None,
+    });
+    let date = timestamps::local_date(message.time);
+    if previous.map(timestamps::local_date) != Some(date) {
+        // The newline puts the separator in a row of its own
+        spans.push(Span::styled(timestamps::day_separator(date) + "\n", theme.timestamp));
+    }
+    spans.push(Span::styled(timestamps.format(message.time, now) + " ", theme.timestamp));
+    if message.is_all() {
+        spans.extend([
+            Span::styled(format!("{}: ", message.sender), Style::new().bold()),
+            Span::raw(&message.message),
+        ]);
+    } else {
+        let direct = Style::new().fg(theme.direct);
+        let prefix = match &message.recipient {
+            Recipient::Id(to) => format!("[DM to {to}] "),
+            _ => "[DM] ".to_owned(),
+        };
+        spans.extend([
+            Span::styled(prefix, direct),
+            Span::styled(format!("{}: ", message.sender), direct.bold()),
+            Span::styled(&message.message, direct),
+        ]);
+    }
+    let mut line = Line::from(spans);
+    match delivery {
+        Some((_, DeliveryStatus::Pending)) => line.push_span(Span::styled(" …", theme.timestamp)),
+        Some((_, DeliveryStatus::Sent)) => line.push_span(Span::styled(" ✓", theme.timestamp)),
+        Some((_, DeliveryStatus::Failed(reason))) => {
+            line.push_span(Span::styled(format!(" ✗ {reason}"), Color::Red))
         }
+        None => {}
     }
+    line
 }
+
 fn center(area: Rect, horizontal: u16, vertical: u16) -> Rect {
     let horizontal_constraint = Constraint::Percentage(horizontal);
     let vertical_constraint = Constraint::Percentage(vertical);
@@ -382,3 +941,80 @@ fn center(area: Rect, horizontal: u16, vertical: u16) -> Rect {
         .areas(area);
     area
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn connect_form_validation() {
+        assert_eq!(
+            validate_connect_form("Kiss Anna", "127.0.0.1", " 5000 "),
+            Ok(("Kiss Anna".to_owned(), "127.0.0.1:5000".parse().unwrap()))
+        );
+        assert!(validate_connect_form("", "127.0.0.1", "5000").is_err());
+        assert!(validate_connect_form("a:b", "127.0.0.1", "5000").is_err());
+        assert!(validate_connect_form("a,b", "127.0.0.1", "5000").is_err());
+        assert!(validate_connect_form("anna", "", "5000").is_err());
+        assert!(validate_connect_form("anna", "127.0.0.1", "65536").is_err());
+        assert!(validate_connect_form("anna", "127.0.0.1", "port").is_err());
+        assert!(validate_connect_form("anna", "no such host.invalid", "5000").is_err());
+    }
+    #[test]
+    fn day_separators_come_before_the_first_message_of_a_day() {
+        let message = |time| HistoryEntry::Message {
+            message: MessageInformation {
+                sender: "bela".into(),
+                recipient: Recipient::All,
+                message: "hi".into(),
+                time,
+            },
+            delivery: None,
+        };
+        let day = chrono::Local
+            .with_ymd_and_hms(2024, 5, 31, 10, 0, 0)
+            .unwrap()
+            .timestamp() as u64;
+        let history = [
+            message(day),
+            message(day + 60),
+            HistoryEntry::System("joined".into()),
+            message(day + 24 * 60 * 60),
+        ];
+        let rows = |index| -> Vec<String> {
+            let theme = Theme::default();
+            let line = message_line(&history, index, TimestampFormat::Minutes, day, &theme);
+            widgets::message_view::wrap_line(&line, 80)
+                .iter()
+                .map(Line::to_string)
+                .collect()
+        };
+        assert_eq!(rows(0), ["── Friday, 2024-05-31 ──", "10:00 bela: hi"]);
+        assert_eq!(rows(1), ["10:01 bela: hi"]);
+        assert_eq!(rows(3), ["── Saturday, 2024-06-01 ──", "10:00 bela: hi"]);
+    }
+    #[test]
+    fn config_fills_the_connect_form() {
+        let app = App::new(Config::default());
+        assert_eq!(app.username.value(), "");
+        assert!(app.focus.is_focused(AppState::ConnectingToNetwork(ConnectingSelected::Name)));
+
+        let app = App::new(Config {
+            nickname: Some("anna".into()),
+            default_server: Some("home".into()),
+            servers: vec![config::ServerProfile {
+                name: "home".into(),
+                host: "localhost".into(),
+                port: 5000,
+                nickname: Some("Kiss Anna".into()),
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            (app.username.value(), app.ip.value(), app.port.value()),
+            ("Kiss Anna", "localhost", "5000")
+        );
+        assert!(app.focus.is_focused(AppState::ConnectingToNetwork(ConnectingSelected::Connect)));
+    }
+}
@@ -0,0 +1,72 @@
+//! Escaping of message text for the line delimited wire format.
+//!
+//! Frames end at the first newline, so a message can't contain one as is. Before sending,
+//! `\` becomes `\\`, a line feed becomes `\n` and a carriage return becomes `\r`. Receiving
+//! reverses this. Unknown escapes and a lone trailing `\` are kept as they are, so text from
+//! clients that don't escape only changes where it happens to contain one of the sequences.
+
+/// Makes `message` safe to put in a single frame
+pub fn escape(message: &str) -> String {
+    let mut escaped = String::with_capacity(message.len());
+    for c in message.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Restores the text that [`escape`] produced `message` from
+pub fn unescape(message: &str) -> String {
+    let mut unescaped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for message in [
+            "",
+            "plain",
+            "two\nlines",
+            "C:\\new\\folder",
+            "\\n",
+            "a\r\nb\\",
+            "árvíz\n😀",
+        ] {
+            let escaped = escape(message);
+            assert!(!escaped.contains(['\n', '\r']));
+            assert_eq!(unescape(&escaped), message);
+        }
+        assert_eq!(escape("a\\b\nc"), "a\\\\b\\nc");
+    }
+
+    #[test]
+    fn unknown_escapes_are_kept() {
+        assert_eq!(unescape("\\x\\"), "\\x\\");
+        assert_eq!(unescape("a\\nb"), "a\nb");
+    }
+}
@@ -1,33 +1,96 @@
+pub use jedlikchat_protocol as protocol;
+mod escape;
+mod reconnect;
+#[cfg(test)]
+mod test_support;
+#[cfg(test)]
+mod tests;
+
+pub use reconnect::ReconnectPolicy;
+
+use crate::utils;
 use cancel_token::CancelToken;
+use protocol::{ClientFrame, ServerFrame};
+use serde::{Deserialize, Serialize};
 use std::{
     error::Error,
-    io::{BufRead, BufReader, Write},
-    net::{TcpStream, ToSocketAddrs},
-    sync::mpsc::{channel, Receiver, Sender},
-    thread::{self, JoinHandle},
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
 };
 
 pub struct Session {
     name: String,
-    socket: TcpStream,
+    socket: Arc<Mutex<TcpStream>>,
     receive_join: Option<JoinHandle<()>>,
     event_sender: Sender<Event>,
     cancel_token: CancelToken,
+    clock: Clock,
+}
+
+/// Shared by every session, so a message of an earlier session can't take the delivery status
+/// of a new one
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Where the timestamps of messages come from, in seconds since the Unix epoch
+pub type Clock = fn() -> u64;
+
+pub fn system_clock() -> u64 {
+    unix_seconds(SystemTime::now())
+}
+
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// Identifies one of our own messages for the lifetime of the process
+pub type MessageId = u64;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DeliveryStatus {
+    Pending,
+    Sent,
+    Failed(String),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Event {
     Quit,
     UsersList(Vec<String>),
-    MessageSent(MessageInformation),
+    MessageSent {
+        id: MessageId,
+        message: MessageInformation,
+    },
+    Delivery {
+        id: MessageId,
+        status: DeliveryStatus,
+    },
     MessageReceived(MessageInformation),
+    /// The handshake went through, sent on the first connect and after every reconnect
+    Connected,
+    Disconnected {
+        reason: String,
+    },
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct MessageInformation {
     pub sender: String,
     pub recipient: Recipient,
     pub message: String,
+    /// When it was received, or sent for our own messages, in seconds since the Unix epoch
+    pub time: u64,
 }
 impl MessageInformation {
     pub fn is_all(&self) -> bool {
@@ -35,31 +98,58 @@ impl MessageInformation {
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Recipient {
+    /// Broadcast to everyone on the server
     All,
+    /// Direct message to a single user
     Id(String),
+    /// Direct message to us
     This,
 }
 
 impl Session {
-    pub fn new(
+    pub fn with_policy(
         name: &str,
         socket: &str,
         cancel_token: CancelToken,
+        policy: ReconnectPolicy,
     ) -> Result<(Self, Receiver<Event>), Box<dyn Error>> {
-        let address = socket.to_socket_addrs().unwrap().next().unwrap();
-        let mut connection = TcpStream::connect(address)?;
-        let _ = connection.write(format!("ID:{}", name).as_bytes());
+        Self::with_clock(name, socket, cancel_token, policy, system_clock)
+    }
+
+    pub fn with_clock(
+        name: &str,
+        socket: &str,
+        cancel_token: CancelToken,
+        policy: ReconnectPolicy,
+        clock: Clock,
+    ) -> Result<(Self, Receiver<Event>), Box<dyn Error>> {
+        let address = socket
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("no address found for {socket}"))?;
+        let (connection, reader) = connect(address, name)?;
+        log::info!("connected to {address} as {name}");
         let (event_sender, event_receiver) = channel();
+        let socket = Arc::new(Mutex::new(connection));
+        {
+            // Wakes up the reader, it would only notice the token on the next line otherwise
+            let socket = Arc::clone(&socket);
+            cancel_token.on_cancel(move || {
+                let _ = socket.lock().unwrap().shutdown(Shutdown::Both);
+            });
+        }
         let mut active_connection = Session {
             name: name.to_string(),
             cancel_token,
-            socket: connection,
+            socket,
             receive_join: None,
             event_sender: event_sender.clone(),
+            clock,
         };
-        let receive_join = active_connection.start_receiving(event_sender);
+        let receive_join = active_connection.start_receiving(reader, address, policy, event_sender);
 
         Ok((
             Session {
@@ -69,76 +159,213 @@ impl Session {
             event_receiver,
         ))
     }
-    fn start_receiving(&mut self, sender: Sender<Event>) -> JoinHandle<()> {
-        let socket = self.socket.try_clone().unwrap();
-        let reader = BufReader::new(socket);
-
+    fn start_receiving(
+        &mut self,
+        reader: TcpStream,
+        address: SocketAddr,
+        policy: ReconnectPolicy,
+        sender: Sender<Event>,
+    ) -> JoinHandle<()> {
+        let socket = Arc::clone(&self.socket);
+        let name = self.name.clone();
         let exit = self.cancel_token.clone();
+        let clock = self.clock;
 
-        thread::spawn(move || {
-            let lines = reader.lines();
-            for line in lines {
-                if *exit {
-                    sender.send(Event::Quit).unwrap();
+        utils::spawn("network reader", move || {
+            let mut reader = reader;
+            if sender.send(Event::Connected).is_err() {
+                return;
+            }
+            while let Some(reason) = receive(reader, &sender, &exit, clock) {
+                if exit.is_cancelled() {
+                    let _ = sender.send(Event::Quit);
+                    break;
+                }
+                log::warn!("lost the connection to {address}: {reason}");
+                if sender.send(Event::Disconnected { reason }).is_err() {
                     break;
                 }
-                let line = match line {
-                    Ok(line) => line,
-                    Err(e) => panic!("{e}"),
+                let Some((connection, new_reader)) =
+                    reconnect(address, &name, &policy, &sender, &exit)
+                else {
+                    break;
                 };
-                let mut message = line.split(":");
-
-                match message.next().unwrap() {
-                    "MSG" => {
-                        let information;
-                        let mut sender_data =
-                            message.next().unwrap().split(" ").collect::<Vec<&str>>();
-                        if *sender_data.last().unwrap() == "(ALL)" {
-                            sender_data.pop();
-                            information = MessageInformation {
-                                sender: sender_data.iter().fold("".to_owned(), |init, i| init + i),
-                                recipient: Recipient::This,
-                                message: message.next().unwrap().to_string(),
-                            };
-                        } else {
-                            information = MessageInformation {
-                                sender: sender_data.iter().fold("".to_owned(), |init, i| init + i),
-                                recipient: Recipient::This,
-                                message: message.next().unwrap().to_string(),
-                            };
-                        }
-                        sender.send(Event::MessageReceived(information)).unwrap();
+                log::info!("reconnected to {address}");
+                {
+                    let mut current = socket.lock().unwrap();
+                    *current = connection;
+                    // Stopped while reconnecting, only the old connection got shut down
+                    if exit.is_cancelled() {
+                        let _ = current.shutdown(Shutdown::Both);
                     }
-                    "USERS" => sender
-                        .send(Event::UsersList(
-                            message
-                                .next()
-                                .unwrap()
-                                .split(",")
-                                .map(|i| i.to_owned())
-                                .collect(),
-                        ))
-                        .unwrap(),
-                    _ => {}
+                }
+                reader = new_reader;
+                if sender.send(Event::Connected).is_err() {
+                    break;
                 }
             }
         })
     }
-    pub fn send(&self, recipient: Recipient, message: &str) -> Result<usize, Box<dyn Error>> {
-        let message = match recipient.clone() {
-            Recipient::All => format!("ALL:{message}"),
-            Recipient::Id(id) => format!("SEND:{id}:{message}"),
-            Recipient::This => unreachable!(),
+    /// Sends a message and echoes it back through the event channel.
+    ///
+    /// A [`Event::MessageSent`] is emitted before writing, followed by an [`Event::Delivery`]
+    /// with the outcome, both carrying the returned id.
+    pub fn send(&self, recipient: Recipient, message: &str) -> Result<MessageId, Box<dyn Error>> {
+        let frame = match recipient.clone() {
+            Recipient::All => ClientFrame::All(escape::escape(message)),
+            Recipient::Id(id) => ClientFrame::Send {
+                to: id,
+                message: escape::escape(message),
+            },
+            Recipient::This => return Err("can't send to ourselves".into()),
+        };
+        let id = NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed);
+        let _ = self.event_sender.send(Event::MessageSent {
+            id,
+            message: MessageInformation {
+                sender: self.name.to_string(),
+                recipient,
+                message: message.to_owned(),
+                time: (self.clock)(),
+            },
+        });
+        let result = write_frame(&self.socket.lock().unwrap(), &frame);
+        let status = match &result {
+            Ok(_) => DeliveryStatus::Sent,
+            Err(e) => {
+                log::warn!("couldn't send message {id}: {e}");
+                DeliveryStatus::Failed(e.to_string())
+            }
         };
-        (Event::MessageSent(MessageInformation {
-            sender: self.name.to_string(),
-            recipient: recipient.clone(),
-            message: message.clone(),
-        }));
-        Ok(self.socket.try_clone().unwrap().write(message.as_bytes())?)
+        let _ = self.event_sender.send(Event::Delivery { id, status });
+        result?;
+        Ok(id)
     }
 
+    /// Cancels the token and shuts down the connection, the reader thread ends with an
+    /// [`Event::Quit`]
     pub fn stop(&self) {
         self.cancel_token.set();
     }
+
+    /// Stops the session and waits for the reader thread, at most for `timeout`. Returns
+    /// whether it ended in time
+    pub fn shutdown(mut self, timeout: Duration) -> bool {
+        self.stop();
+        self.receive_join
+            .take()
+            .is_none_or(|join| utils::join_timeout(join, timeout, "network reader"))
+    }
+
+    /// Whether events can still come, `false` once stopped or given up on reconnecting
+    pub fn is_running(&self) -> bool {
+        self.receive_join
+            .as_ref()
+            .is_some_and(|join| !join.is_finished())
+    }
+}
+
+/// Writes a single newline terminated frame, returns the number of bytes written. The
+/// terminator is part of the wire format, see [`protocol`]
+fn write_frame(mut socket: &TcpStream, frame: &ClientFrame) -> io::Result<usize> {
+    let line = format!("{frame}\n");
+    socket.write_all(line.as_bytes())?;
+    Ok(line.len())
+}
+
+/// Connects and sends the `ID:` handshake, returns the writing and reading half
+fn connect(address: SocketAddr, name: &str) -> io::Result<(TcpStream, TcpStream)> {
+    let connection = TcpStream::connect(address)?;
+    write_frame(&connection, &ClientFrame::Id(name.to_owned()))?;
+    let reader = connection.try_clone()?;
+    Ok((connection, reader))
+}
+
+/// Forwards incoming frames until the connection is lost, returns why it was lost.
+///
+/// Returns `None` if the session was stopped or nobody is listening for events anymore.
+fn receive(
+    reader: TcpStream,
+    sender: &Sender<Event>,
+    exit: &CancelToken,
+    clock: Clock,
+) -> Option<String> {
+    for line in BufReader::new(reader).lines() {
+        if exit.is_cancelled() {
+            let _ = sender.send(Event::Quit);
+            return None;
+        }
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Some(e.to_string()),
+        };
+        // Malformed lines are dropped, a misbehaving server shouldn't kill the reader
+        let Ok(frame) = line.parse::<ServerFrame>() else {
+            log::debug!("dropped a malformed line: {line:?}");
+            continue;
+        };
+
+        let event = match frame {
+            ServerFrame::Msg {
+                sender: from,
+                broadcast,
+                message,
+            } => Event::MessageReceived(MessageInformation {
+                sender: from,
+                recipient: if broadcast {
+                    Recipient::All
+                } else {
+                    Recipient::This
+                },
+                message: escape::unescape(&message),
+                time: clock(),
+            }),
+            ServerFrame::Users(users) => Event::UsersList(users),
+            ServerFrame::Unknown(_) => continue,
+        };
+        if sender.send(event).is_err() {
+            return None;
+        }
+    }
+    Some("connection closed by the server".to_owned())
+}
+
+fn reconnect(
+    address: SocketAddr,
+    name: &str,
+    policy: &ReconnectPolicy,
+    sender: &Sender<Event>,
+    exit: &CancelToken,
+) -> Option<(TcpStream, TcpStream)> {
+    let mut attempt = 1;
+    while policy.allows(attempt) {
+        let delay = policy.delay(attempt);
+        log::debug!("reconnect attempt {attempt} in {delay:?}");
+        if sender.send(Event::Reconnecting { attempt, delay }).is_err() {
+            return None;
+        }
+        if !sleep_unless_cancelled(delay, exit) {
+            let _ = sender.send(Event::Quit);
+            return None;
+        }
+        if let Ok(connection) = connect(address, name) {
+            return Some(connection);
+        }
+        attempt += 1;
+    }
+    if attempt > 1 {
+        log::warn!(
+            "gave up reconnecting to {address} after {} attempts",
+            attempt - 1
+        );
+        let _ = sender.send(Event::Disconnected {
+            reason: format!("gave up after {} attempts", attempt - 1),
+        });
+    }
+    None
+}
+
+/// Returns false if the token got cancelled before the delay was over
+fn sleep_unless_cancelled(delay: Duration, exit: &CancelToken) -> bool {
+    !exit.wait_timeout(delay)
 }
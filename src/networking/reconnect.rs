@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+/// Controls how a [`Session`](super::Session) tries to get back online after losing the connection.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReconnectPolicy {
+    /// Give up after this many failed attempts, `None` retries forever
+    pub max_attempts: Option<u32>,
+    /// Delay before the first attempt, doubled for every following one
+    pub initial_delay: Duration,
+    /// Upper bound for the delay before jitter is applied
+    pub max_delay: Duration,
+    /// Fraction of the delay that is randomly added or subtracted, between 0.0 and 1.0
+    pub jitter: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: Some(10),
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Never reconnect, a dropped connection ends the session
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: Some(0),
+            ..Default::default()
+        }
+    }
+
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt <= max)
+    }
+
+    /// Delay before the given attempt (starting at 1), with jitter applied
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.delay_with(attempt, fastrand::f64())
+    }
+
+    /// `random` is expected to be in `0.0..1.0`, it picks the point in the jitter range
+    fn delay_with(&self, attempt: u32, random: f64) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let base = self
+            .initial_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        base.mul_f64(1.0 - jitter + 2.0 * jitter * random)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: Some(3),
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: 0.5,
+        }
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_cap() {
+        let policy = ReconnectPolicy {
+            jitter: 0.0,
+            ..policy()
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+        assert_eq!(policy.delay(5), Duration::from_secs(1));
+        assert_eq!(policy.delay(200), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_stays_in_range() {
+        let policy = policy();
+        assert_eq!(policy.delay_with(2, 0.0), Duration::from_millis(100));
+        assert_eq!(policy.delay_with(2, 0.5), Duration::from_millis(200));
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(300));
+        }
+    }
+
+    #[test]
+    fn attempt_limit() {
+        let policy = policy();
+        assert!(policy.allows(3));
+        assert!(!policy.allows(4));
+        assert!(!ReconnectPolicy::disabled().allows(1));
+        let unlimited = ReconnectPolicy {
+            max_attempts: None,
+            ..policy
+        };
+        assert!(unlimited.allows(u32::MAX));
+    }
+}
@@ -0,0 +1,112 @@
+//! Scripted fake server for driving a [`Session`](super::Session) in tests.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::mpsc::{channel, Receiver},
+    thread,
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Step {
+    Expect(String),
+    Send(String),
+    Disconnect,
+}
+
+#[derive(Default)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    /// The next line the client sends has to be exactly `line`
+    pub fn expect(mut self, line: &str) -> Self {
+        self.steps.push(Step::Expect(line.to_owned()));
+        self
+    }
+
+    /// Sends `line` to the client, the newline is added automatically
+    pub fn send(mut self, line: &str) -> Self {
+        self.steps.push(Step::Send(line.to_owned()));
+        self
+    }
+
+    /// Closes the connection, otherwise it's kept open until the client hangs up
+    pub fn disconnect(mut self) -> Self {
+        self.steps.push(Step::Disconnect);
+        self
+    }
+
+    /// Listens on a random local port and plays the script to the first client
+    pub fn start(self) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (result_sender, result) = channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let outcome = play(self.steps, &stream, &mut reader);
+            let disconnected = matches!(outcome, Ok(true));
+            let _ = result_sender.send(outcome.map(|_| ()));
+            if !disconnected {
+                // Hold on to the connection so the client doesn't see an unexpected hang up
+                let _ = stream.set_read_timeout(None);
+                let _ = reader.lines().count();
+            }
+        });
+        MockServer { address, result }
+    }
+}
+
+/// Returns whether the script closed the connection
+fn play(
+    steps: Vec<Step>,
+    mut stream: &TcpStream,
+    reader: &mut BufReader<TcpStream>,
+) -> Result<bool, String> {
+    for step in steps {
+        match step {
+            Step::Expect(expected) => {
+                let mut line = String::new();
+                reader
+                    .read_line(&mut line)
+                    .map_err(|e| format!("expected {expected:?}, got error: {e}"))?;
+                if line.trim_end_matches('\n') != expected {
+                    return Err(format!("expected {expected:?}, got {line:?}"));
+                }
+            }
+            Step::Send(line) => {
+                writeln!(stream, "{line}").map_err(|e| format!("couldn't send {line:?}: {e}"))?
+            }
+            Step::Disconnect => {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+pub struct MockServer {
+    address: SocketAddr,
+    result: Receiver<Result<(), String>>,
+}
+
+impl MockServer {
+    pub fn address(&self) -> String {
+        self.address.to_string()
+    }
+
+    /// Waits for the script to run to the end, panics if the client didn't follow it
+    pub fn finish(self) {
+        match self.result.recv_timeout(TIMEOUT) {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => panic!("mock server: {e}"),
+            Err(_) => panic!("mock server: script didn't finish in time"),
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use super::test_support::{MockServer, Script};
+use super::*;
+use std::net::TcpListener;
+use std::time::Instant;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const NOW: u64 = 1_717_156_800;
+
+fn fixed_clock() -> u64 {
+    NOW
+}
+
+fn read_line(stream: &TcpStream) -> String {
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    line
+}
+
+#[test]
+fn reconnects_after_server_restart() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let policy = ReconnectPolicy {
+        max_attempts: Some(20),
+        initial_delay: Duration::from_millis(20),
+        max_delay: Duration::from_millis(100),
+        jitter: 0.0,
+    };
+    let (session, events) =
+        Session::with_policy("anna", &address.to_string(), CancelToken::new(), policy).unwrap();
+
+    let (stream, _) = listener.accept().unwrap();
+    assert_eq!(read_line(&stream), "ID:anna\n");
+    assert_eq!(events.recv_timeout(TIMEOUT).unwrap(), Event::Connected);
+
+    // Kill the server completely, then bring it back on the same port
+    drop(stream);
+    drop(listener);
+    assert!(matches!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::Disconnected { .. }
+    ));
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::Reconnecting {
+            attempt: 1,
+            delay: Duration::from_millis(20)
+        }
+    );
+    let listener = TcpListener::bind(address).unwrap();
+
+    let (stream, _) = listener.accept().unwrap();
+    assert_eq!(read_line(&stream), "ID:anna\n");
+    let event = events
+        .iter()
+        .find(|event| !matches!(event, Event::Reconnecting { .. }))
+        .unwrap();
+    assert_eq!(event, Event::Connected);
+
+    session.send(Recipient::All, "back").unwrap();
+    assert_eq!(read_line(&stream), "ALL:back\n");
+    session.stop();
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let policy = ReconnectPolicy {
+        max_attempts: Some(2),
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(10),
+        jitter: 0.0,
+    };
+    let (_session, events) =
+        Session::with_policy("anna", &address.to_string(), CancelToken::new(), policy).unwrap();
+    drop(listener.accept().unwrap());
+    drop(listener);
+
+    let received: Vec<Event> = (0..5)
+        .map(|_| events.recv_timeout(TIMEOUT).unwrap())
+        .collect();
+    assert_eq!(received[0], Event::Connected);
+    assert!(matches!(received[1], Event::Disconnected { .. }));
+    assert!(matches!(
+        received[2],
+        Event::Reconnecting { attempt: 1, .. }
+    ));
+    assert!(matches!(
+        received[3],
+        Event::Reconnecting { attempt: 2, .. }
+    ));
+    assert_eq!(
+        received[4],
+        Event::Disconnected {
+            reason: "gave up after 2 attempts".into()
+        }
+    );
+    assert!(events.recv_timeout(Duration::from_millis(100)).is_err());
+}
+
+fn start(server: &MockServer) -> (Session, Receiver<Event>) {
+    let (session, events) = Session::with_clock(
+        "Kiss Anna",
+        &server.address(),
+        CancelToken::new(),
+        ReconnectPolicy::disabled(),
+        fixed_clock,
+    )
+    .unwrap();
+    assert_eq!(events.recv_timeout(TIMEOUT).unwrap(), Event::Connected);
+    (session, events)
+}
+
+#[test]
+fn handshake_and_sent_frames() {
+    let server = Script::default()
+        .expect("ID:Kiss Anna")
+        .expect("ALL:hello: all")
+        .expect("SEND:bela:psst")
+        .start();
+    let (session, _events) = start(&server);
+
+    session.send(Recipient::All, "hello: all").unwrap();
+    session.send(Recipient::Id("bela".into()), "psst").unwrap();
+    server.finish();
+}
+
+#[test]
+fn receives_broadcast_and_direct_messages() {
+    let server = Script::default()
+        .expect("ID:Kiss Anna")
+        .send("MSG:Nagy Bela (ALL):hi: all")
+        .send("MSG:cili:just you")
+        .start();
+    let (_session, events) = start(&server);
+
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::MessageReceived(MessageInformation {
+            sender: "Nagy Bela".into(),
+            recipient: Recipient::All,
+            message: "hi: all".into(),
+            time: NOW,
+        })
+    );
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::MessageReceived(MessageInformation {
+            sender: "cili".into(),
+            recipient: Recipient::This,
+            message: "just you".into(),
+            time: NOW,
+        })
+    );
+    server.finish();
+}
+
+#[test]
+fn multi_line_messages_are_escaped() {
+    let server = Script::default()
+        .expect("ID:Kiss Anna")
+        .expect("ALL:first\\nsecond \\\\o/")
+        .send("MSG:cili (ALL):one\\ntwo")
+        .start();
+    let (session, events) = start(&server);
+
+    session.send(Recipient::All, "first\nsecond \\o/").unwrap();
+    // The server doesn't echo broadcasts, our own message only comes as MessageSent and Delivery
+    let received = std::iter::from_fn(|| events.recv_timeout(TIMEOUT).ok())
+        .find(|event| matches!(event, Event::MessageReceived(_)))
+        .unwrap();
+    assert_eq!(
+        received,
+        Event::MessageReceived(MessageInformation {
+            sender: "cili".into(),
+            recipient: Recipient::All,
+            message: "one\ntwo".into(),
+            time: NOW,
+        })
+    );
+    server.finish();
+}
+
+#[test]
+fn user_list_updates() {
+    let server = Script::default()
+        .expect("ID:Kiss Anna")
+        .send("USERS:Kiss Anna")
+        .send("not a frame")
+        .send("USERS:Kiss Anna,bela")
+        .send("USERS:")
+        .start();
+    let (_session, events) = start(&server);
+
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::UsersList(vec!["Kiss Anna".into()])
+    );
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::UsersList(vec!["Kiss Anna".into(), "bela".into()])
+    );
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::UsersList(vec![])
+    );
+    server.finish();
+}
+
+#[test]
+fn server_disconnect() {
+    let server = Script::default()
+        .expect("ID:Kiss Anna")
+        .send("USERS:Kiss Anna")
+        .disconnect()
+        .start();
+    let (_session, events) = start(&server);
+
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::UsersList(vec!["Kiss Anna".into()])
+    );
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::Disconnected {
+            reason: "connection closed by the server".into()
+        }
+    );
+    server.finish();
+}
+
+#[test]
+fn stop_ends_the_session() {
+    let server = Script::default().expect("ID:Kiss Anna").start();
+    let (session, events) = start(&server);
+    server.finish();
+
+    // The server stays quiet, closing the connection is what wakes up the reader
+    session.stop();
+    assert!(session.send(Recipient::All, "too late").is_err());
+    assert!(session.shutdown(TIMEOUT));
+    // The reader and the failed send report at the same time, so their order isn't checked
+    let events: Vec<_> = events.iter().collect();
+    assert_eq!(events.len(), 3, "{events:?}");
+    assert!(events.contains(&Event::Quit));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, Event::MessageSent { .. })));
+    assert!(events.iter().any(|event| matches!(
+        event,
+        Event::Delivery {
+            status: DeliveryStatus::Failed(_),
+            ..
+        }
+    )));
+}
+
+#[test]
+fn shutdown_is_prompt_with_an_idle_server() {
+    let server = Script::default().expect("ID:Kiss Anna").start();
+    let (session, events) = start(&server);
+    server.finish();
+
+    let started = Instant::now();
+    assert!(session.shutdown(TIMEOUT));
+    assert!(started.elapsed() < Duration::from_millis(500));
+    // Everything that could send events is gone
+    assert_eq!(events.iter().collect::<Vec<_>>(), [Event::Quit]);
+}
+
+#[test]
+fn stopping_the_parent_token_stops_the_session() {
+    let server = Script::default().expect("ID:Kiss Anna").start();
+    let app = CancelToken::new();
+    let (session, events) = Session::with_policy(
+        "Kiss Anna",
+        &server.address(),
+        app.child(),
+        ReconnectPolicy::default(),
+    )
+    .unwrap();
+    assert_eq!(events.recv_timeout(TIMEOUT).unwrap(), Event::Connected);
+    server.finish();
+
+    app.set();
+    assert_eq!(events.recv_timeout(TIMEOUT).unwrap(), Event::Quit);
+    assert!(session.shutdown(TIMEOUT));
+}
+
+#[test]
+fn sent_messages_are_echoed() {
+    let server = Script::default()
+        .expect("ID:Kiss Anna")
+        .expect("SEND:bela:10:30?")
+        .start();
+    let (session, events) = start(&server);
+
+    // Nothing goes out, so the first event is still the real message
+    assert!(session.send(Recipient::This, "me").is_err());
+    let id = session
+        .send(Recipient::Id("bela".into()), "10:30?")
+        .unwrap();
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::MessageSent {
+            id,
+            message: MessageInformation {
+                sender: "Kiss Anna".into(),
+                recipient: Recipient::Id("bela".into()),
+                message: "10:30?".into(),
+                time: NOW,
+            }
+        }
+    );
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        Event::Delivery {
+            id,
+            status: DeliveryStatus::Sent
+        }
+    );
+    server.finish();
+}
+
+#[test]
+fn message_ids_are_unique_across_sessions() {
+    let ids: Vec<_> = (0..2)
+        .map(|_| {
+            let server = Script::default()
+                .expect("ID:Kiss Anna")
+                .expect("ALL:hi")
+                .start();
+            let (session, _events) = start(&server);
+            let id = session.send(Recipient::All, "hi").unwrap();
+            server.finish();
+            id
+        })
+        .collect();
+    assert_ne!(ids[0], ids[1]);
+}
+
+#[test]
+fn failed_write_is_reported() {
+    let server = Script::default().expect("ID:Kiss Anna").start();
+    let (session, events) = start(&server);
+    server.finish();
+
+    session
+        .socket
+        .lock()
+        .unwrap()
+        .shutdown(std::net::Shutdown::Write)
+        .unwrap();
+    assert!(session.send(Recipient::All, "lost").is_err());
+    // The server hangs up once our side is closed, that's not what's being tested here
+    let mut events = events
+        .iter()
+        .filter(|event| !matches!(event, Event::Disconnected { .. }));
+    assert!(matches!(events.next(), Some(Event::MessageSent { .. })));
+    assert!(matches!(
+        events.next(),
+        Some(Event::Delivery {
+            status: DeliveryStatus::Failed(_),
+            ..
+        })
+    ));
+}
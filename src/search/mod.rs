@@ -0,0 +1,318 @@
+use crate::chat_log::ChatLog;
+use crate::conversations::{Conversations, HistoryEntry};
+use crate::networking::{MessageInformation, Recipient};
+use chrono::{Local, NaiveDate, TimeZone};
+use regex::Regex;
+use std::io;
+use std::ops::Range;
+
+/// A parsed search like `lunch from:bela in:everyone after:2024-05-01 before:2024-06-01`.
+///
+/// The text is matched as a substring ignoring case, or as a regular expression if it's
+/// between slashes. Filter values with spaces can be put in double quotes.
+#[derive(Debug)]
+pub struct Query {
+    /// `None` matches every message, only the filters count then
+    pattern: Option<Regex>,
+    sender: Option<String>,
+    conversation: Option<Recipient>,
+    /// Unix seconds, inclusive
+    after: Option<u64>,
+    /// Unix seconds, exclusive
+    before: Option<u64>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut query = Query {
+            pattern: None,
+            sender: None,
+            conversation: None,
+            after: None,
+            before: None,
+        };
+        let mut text = vec![];
+        for token in tokens(input)? {
+            match token.split_once(':') {
+                Some(("from", sender)) => query.sender = Some(sender.to_owned()),
+                Some(("in", "everyone" | "all")) => query.conversation = Some(Recipient::All),
+                Some(("in", name)) => query.conversation = Some(Recipient::Id(name.to_owned())),
+                Some(("after", date)) => query.after = Some(start_of_day(date)?),
+                Some(("before", date)) => query.before = Some(start_of_day(date)?),
+                _ => text.push(token),
+            }
+        }
+        let text = text.join(" ");
+        let pattern = match text
+            .strip_prefix('/')
+            .and_then(|text| text.strip_suffix('/'))
+        {
+            Some(regex) => regex.to_owned(),
+            None => format!("(?i){}", regex::escape(&text)),
+        };
+        if !text.is_empty() {
+            let regex = Regex::new(&pattern).map_err(|e| format!("Invalid regex: {e}"))?;
+            query.pattern = Some(regex);
+        }
+        Ok(query)
+    }
+
+    /// Byte ranges of the matches in the message, `None` if it doesn't match
+    pub fn matches(
+        &self,
+        conversation: &Recipient,
+        message: &MessageInformation,
+    ) -> Option<Vec<Range<usize>>> {
+        if self
+            .sender
+            .as_ref()
+            .is_some_and(|sender| !sender.eq_ignore_ascii_case(&message.sender))
+            || self
+                .conversation
+                .as_ref()
+                .is_some_and(|c| c != conversation)
+            || self.after.is_some_and(|after| message.time < after)
+            || self.before.is_some_and(|before| message.time >= before)
+        {
+            return None;
+        }
+        let Some(pattern) = &self.pattern else {
+            return Some(vec![]);
+        };
+        let matches: Vec<_> = pattern
+            .find_iter(&message.message)
+            .map(|found| found.range())
+            .filter(|range| !range.is_empty())
+            .collect();
+        (!matches.is_empty()).then_some(matches)
+    }
+}
+
+/// Splits on whitespace, except between double quotes
+fn tokens(input: &str) -> Result<Vec<String>, String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in input.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if quoted {
+        return Err("Missing closing quote".to_owned());
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Local midnight at the start of a `YYYY-MM-DD` date, in Unix seconds
+fn start_of_day(date: &str) -> Result<u64, String> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| format!("Dates look like 2024-05-31, not {date}"))?;
+    let midnight = Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .ok_or_else(|| format!("{date} has no midnight in the local time zone"))?;
+    Ok(midnight.timestamp().max(0) as u64)
+}
+
+/// Where a hit is
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Location {
+    /// Index in the history of the conversation
+    History(usize),
+    /// Index in the chat log of the conversation, for messages that weren't loaded
+    Log(usize),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Hit {
+    pub conversation: Recipient,
+    pub location: Location,
+    pub message: MessageInformation,
+    /// Byte ranges of the matches in the text of the message
+    pub matches: Vec<Range<usize>>,
+}
+
+/// The messages of a conversation that are only in the chat log, oldest first
+pub type Unloaded = (Recipient, Vec<MessageInformation>);
+
+/// Reads the messages of every conversation that weren't loaded from the log, once per search
+/// instead of on every keystroke
+pub fn read_unloaded(conversations: &Conversations, log: &ChatLog) -> io::Result<Vec<Unloaded>> {
+    let mut unloaded = vec![];
+    for conversation in conversations.iter() {
+        if conversation.unloaded == 0 {
+            continue;
+        }
+        let mut messages = log.read(&conversation.recipient)?;
+        messages.truncate(conversation.unloaded);
+        unloaded.push((conversation.recipient.clone(), messages));
+    }
+    Ok(unloaded)
+}
+
+/// Searches the loaded histories and the older messages read by [`read_unloaded`], newest
+/// first
+pub fn search(query: &Query, conversations: &Conversations, unloaded: &[Unloaded]) -> Vec<Hit> {
+    let mut hits = vec![];
+    for conversation in conversations.iter() {
+        let recipient = &conversation.recipient;
+        let older = unloaded
+            .iter()
+            .find(|(of, _)| of == recipient)
+            .map_or(&[][..], |(_, messages)| messages);
+        for (index, message) in older.iter().enumerate() {
+            if let Some(matches) = query.matches(recipient, message) {
+                hits.push(Hit {
+                    conversation: recipient.clone(),
+                    location: Location::Log(index),
+                    message: message.clone(),
+                    matches,
+                });
+            }
+        }
+        for (index, entry) in conversation.history.iter().enumerate() {
+            let HistoryEntry::Message { message, .. } = entry else {
+                continue;
+            };
+            if let Some(matches) = query.matches(recipient, message) {
+                hits.push(Hit {
+                    conversation: recipient.clone(),
+                    location: Location::History(index),
+                    message: message.clone(),
+                    matches,
+                });
+            }
+        }
+    }
+    // Stable, so hits from the same second stay in order
+    hits.reverse();
+    hits.sort_by_key(|hit| std::cmp::Reverse(hit.message.time));
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(sender: &str, recipient: Recipient, text: &str) -> MessageInformation {
+        MessageInformation {
+            sender: sender.into(),
+            recipient,
+            message: text.into(),
+            time: 0,
+        }
+    }
+
+    fn at(time: u64, message: &MessageInformation) -> MessageInformation {
+        MessageInformation {
+            time,
+            ..message.clone()
+        }
+    }
+
+    #[test]
+    fn substring_ignores_case() {
+        let query = Query::parse("ÁRVÍZ").unwrap();
+        let found = query.matches(
+            &Recipient::All,
+            &message("bela", Recipient::All, "az árvíz, árvíz"),
+        );
+        assert_eq!(found, Some(vec![3..10, 12..19]));
+        assert_eq!(
+            query.matches(&Recipient::All, &message("bela", Recipient::All, "nope")),
+            None
+        );
+    }
+
+    #[test]
+    fn regex_between_slashes() {
+        let query = Query::parse("/\\d+:\\d+/").unwrap();
+        let found = query.matches(
+            &Recipient::All,
+            &message("bela", Recipient::All, "at 10:30 or 11:00"),
+        );
+        assert_eq!(found, Some(vec![3..8, 12..17]));
+        assert!(Query::parse("/(unclosed/")
+            .unwrap_err()
+            .starts_with("Invalid regex"));
+        // Special characters are literal outside of slashes
+        let query = Query::parse("a.b").unwrap();
+        assert!(query
+            .matches(&Recipient::All, &message("bela", Recipient::All, "axb"))
+            .is_none());
+    }
+
+    #[test]
+    fn filters() {
+        let query =
+            Query::parse("from:\"Kiss Anna\" in:bela after:2024-05-01 before:2024-05-02").unwrap();
+        let dm = Recipient::Id("bela".into());
+        let day = start_of_day("2024-05-01").unwrap();
+        let psst = message("kiss anna", Recipient::Id("bela".into()), "psst");
+        assert_eq!(query.matches(&dm, &at(day + 60, &psst)), Some(vec![]));
+        assert_eq!(query.matches(&Recipient::All, &at(day + 60, &psst)), None);
+        assert_eq!(query.matches(&dm, &at(day - 1, &psst)), None);
+        assert_eq!(query.matches(&dm, &at(day + 24 * 60 * 60, &psst)), None);
+        let from_bela = message("bela", Recipient::This, "psst");
+        assert_eq!(query.matches(&dm, &at(day + 60, &from_bela)), None);
+
+        let everyone = Query::parse("in:everyone").unwrap();
+        assert!(everyone.matches(&Recipient::All, &psst).is_some());
+        assert!(Query::parse("after:yesterday").is_err());
+        assert!(Query::parse("from:\"Kiss Anna").is_err());
+    }
+
+    #[test]
+    fn searches_history_and_unloaded_log() {
+        let root = tempfile::tempdir().unwrap();
+        let log = ChatLog::open(root.path(), "host:1", "anna").unwrap();
+        let mut conversations = Conversations::default();
+        let messages: Vec<_> = (0..4)
+            .map(|i| at(i, &message("bela", Recipient::All, &format!("lunch {i}"))))
+            .collect();
+        for message in &messages {
+            log.append(&Recipient::All, message).unwrap();
+        }
+        // The last two are loaded
+        conversations.restore(&Recipient::All, messages[2..].to_vec(), 2);
+        conversations.push(
+            at(10, &message("cili", Recipient::This, "Lunch?")),
+            None,
+            "anna",
+        );
+        conversations.push_system("lunch is a system line here".into());
+
+        let unloaded = read_unloaded(&conversations, &log).unwrap();
+        assert_eq!(unloaded, [(Recipient::All, messages[..2].to_vec())]);
+        let query = Query::parse("lunch").unwrap();
+        let hits = search(&query, &conversations, &unloaded);
+        let found: Vec<_> = hits
+            .iter()
+            .map(|hit| (hit.message.time, hit.location))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (10, Location::History(0)),
+                (3, Location::History(1)),
+                (2, Location::History(0)),
+                (1, Location::Log(1)),
+                (0, Location::Log(0)),
+            ]
+        );
+        assert_eq!(hits[0].conversation, Recipient::Id("cili".into()));
+
+        // Without the log only the loaded messages are searched
+        assert_eq!(search(&query, &conversations, &[]).len(), 3);
+    }
+}
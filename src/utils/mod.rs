@@ -1,8 +1,40 @@
-use std::io::{stdin, BufRead};
+use std::io::{self, stdin, BufRead};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-pub fn read() -> String {
+/// One line of stdin with its line ending, empty at the end of the input
+pub fn read() -> io::Result<String> {
     let stdin = stdin();
     let mut lines = String::new();
-    stdin.lock().read_line(&mut lines).unwrap();
-    lines
+    stdin.lock().read_line(&mut lines)?;
+    Ok(lines)
+}
+
+/// Like [`thread::spawn`], but the name shows up in panics, crash reports and the log
+pub fn spawn<T: Send + 'static>(
+    name: &str,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> JoinHandle<T> {
+    thread::Builder::new()
+        .name(name.to_owned())
+        .spawn(f)
+        .expect("failed to spawn thread")
+}
+
+/// Joins the thread if it ends within `timeout`, otherwise leaves it running. Returns whether it
+/// ended, both outcomes and panics are logged under `name`
+pub fn join_timeout<T>(handle: JoinHandle<T>, timeout: Duration, name: &str) -> bool {
+    let start = Instant::now();
+    while !handle.is_finished() {
+        if start.elapsed() >= timeout {
+            log::warn!("the {name} thread didn't stop within {timeout:?}, leaving it running");
+            return false;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    match handle.join() {
+        Ok(_) => log::debug!("the {name} thread stopped after {:?}", start.elapsed()),
+        Err(_) => log::error!("the {name} thread panicked"),
+    }
+    true
 }
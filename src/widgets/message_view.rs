@@ -0,0 +1,465 @@
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{StatefulWidget, Widget};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A wrapped row inside a message, `(message index, row index)`
+type Position = (usize, usize);
+
+/// Scroll state of a [`MessageView`].
+///
+/// Scrolling is only recorded here and resolved on the next render, since that's when the
+/// width and the messages needed to wrap them are known.
+#[derive(Default)]
+pub struct MessageViewState {
+    /// First visible row, `None` sticks to the end of the history
+    top: Option<Position>,
+    /// Rows to move on the next render, negative is up
+    pending: isize,
+    /// Messages that arrived while scrolled up
+    unseen: usize,
+    /// Height of the last render, used for paging
+    height: usize,
+}
+
+impl MessageViewState {
+    pub fn message_added(&mut self) {
+        if self.top.is_some() {
+            self.unseen += 1;
+        }
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.pending -= rows as isize;
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.pending += rows as isize;
+    }
+
+    pub fn page_up(&mut self) {
+        self.scroll_up(self.height.max(1));
+    }
+
+    pub fn page_down(&mut self) {
+        self.scroll_down(self.height.max(1));
+    }
+
+    /// Scrolls the message with the given index to the top
+    pub fn show_message(&mut self, message: usize) {
+        self.top = Some((message, 0));
+        self.pending = 0;
+    }
+
+    pub fn scroll_to_top(&mut self) {
+        self.top = Some((0, 0));
+        self.pending = 0;
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.top = None;
+        self.pending = 0;
+        self.unseen = 0;
+    }
+}
+
+/// Renders a chat history bottom up, only wrapping the messages that end up on screen.
+pub struct MessageView<F> {
+    len: usize,
+    line: F,
+}
+
+impl<'a, F: Fn(usize) -> Line<'a>> MessageView<F> {
+    /// `line` produces the unwrapped line of the message with the given index
+    pub fn new(len: usize, line: F) -> Self {
+        Self { len, line }
+    }
+
+    fn rows(&self, message: usize, width: usize) -> Vec<Line<'static>> {
+        wrap_line(&(self.line)(message), width)
+    }
+
+    /// Top of the viewport when showing the end of the history
+    fn bottom_top(&self, width: usize, height: usize) -> Position {
+        let mut remaining = height;
+        for message in (0..self.len).rev() {
+            let rows = self.rows(message, width).len();
+            if rows >= remaining {
+                return (message, rows - remaining);
+            }
+            remaining -= rows;
+        }
+        (0, 0)
+    }
+
+    fn move_up(&self, (mut message, mut row): Position, mut by: usize, width: usize) -> Position {
+        loop {
+            if row >= by {
+                return (message, row - by);
+            }
+            if message == 0 {
+                return (0, 0);
+            }
+            by -= row + 1;
+            message -= 1;
+            row = self.rows(message, width).len() - 1;
+        }
+    }
+
+    /// Returns `None` once the end of the history gets in view
+    fn move_down(
+        &self,
+        (mut message, mut row): Position,
+        mut by: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<Position> {
+        while message < self.len {
+            let rows = self.rows(message, width).len();
+            if row + by < rows {
+                row += by;
+                break;
+            }
+            by -= rows - row;
+            message += 1;
+            row = 0;
+        }
+        if message >= self.len || !self.fills(message, row, width, height) {
+            return None;
+        }
+        Some((message, row))
+    }
+
+    /// Whether there are enough rows from the position to the end to fill the viewport
+    fn fills(&self, mut message: usize, row: usize, width: usize, height: usize) -> bool {
+        let mut available = self.rows(message, width).len().saturating_sub(row);
+        while available < height {
+            message += 1;
+            if message >= self.len {
+                return false;
+            }
+            available += self.rows(message, width).len();
+        }
+        true
+    }
+
+    fn resolve_top(&self, state: &mut MessageViewState, width: usize, height: usize) {
+        if self.len == 0 {
+            state.top = None;
+            state.pending = 0;
+            return;
+        }
+        let pending = std::mem::take(&mut state.pending);
+        // The history or the width may have changed since the position was stored
+        let top = state.top.map(|(message, row)| {
+            let message = message.min(self.len - 1);
+            (message, row.min(self.rows(message, width).len() - 1))
+        });
+        if pending < 0 {
+            let top = top.unwrap_or_else(|| self.bottom_top(width, height));
+            state.top = Some(self.move_up(top, pending.unsigned_abs(), width));
+        } else if let Some(top) = top {
+            state.top = self.move_down(top, pending as usize, width, height);
+        }
+        if state.top.is_none() {
+            state.unseen = 0;
+        }
+    }
+}
+
+impl<'a, F: Fn(usize) -> Line<'a>> StatefulWidget for MessageView<F> {
+    type State = MessageViewState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let width = area.width as usize;
+        let height = area.height as usize;
+        state.height = height;
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.resolve_top(state, width, height);
+
+        let mut visible = Vec::with_capacity(height);
+        match state.top {
+            Some((message, row)) => {
+                for message in message..self.len {
+                    visible.extend(self.rows(message, width));
+                    if visible.len() >= height + row {
+                        break;
+                    }
+                }
+                visible.drain(..row.min(visible.len()));
+                visible.truncate(height);
+            }
+            None => {
+                for message in (0..self.len).rev() {
+                    let mut rows = self.rows(message, width);
+                    rows.append(&mut visible);
+                    visible = rows;
+                    if visible.len() >= height {
+                        break;
+                    }
+                }
+                visible.drain(..visible.len().saturating_sub(height));
+            }
+        }
+
+        for (y, row) in visible.iter().enumerate() {
+            buf.set_line(area.x, area.y + y as u16, row, area.width);
+        }
+
+        if state.top.is_some() && state.unseen > 0 {
+            let marker = Line::from(format!(" ↓ {} new ", state.unseen))
+                .style(Style::new().fg(Color::Black).bg(Color::Yellow).bold())
+                .centered();
+            let bottom = Rect {
+                y: area.bottom() - 1,
+                height: 1,
+                ..area
+            };
+            buf.set_style(bottom, Style::reset());
+            marker.render(bottom, buf);
+        }
+    }
+}
+
+/// Word wraps a styled line to `width` columns, words longer than a row get broken up
+pub fn wrap_line(line: &Line, width: usize) -> Vec<Line<'static>> {
+    let mut wrapper = Wrapper {
+        width: width.max(1),
+        rows: vec![],
+        row: vec![],
+        row_width: 0,
+    };
+    // A word is only placed once it's complete, so it can move to the next row as a whole
+    let mut word = vec![];
+    for span in &line.spans {
+        let style = line.style.patch(span.style);
+        for grapheme in span.content.graphemes(true) {
+            if is_newline(grapheme) {
+                wrapper.push_word(&mut word);
+                wrapper
+                    .rows
+                    .push(Line::from(std::mem::take(&mut wrapper.row)));
+                wrapper.row_width = 0;
+                continue;
+            }
+            word.push((grapheme, style));
+            if grapheme == " " {
+                wrapper.push_word(&mut word);
+            }
+        }
+    }
+    wrapper.push_word(&mut word);
+    wrapper.rows.push(Line::from(wrapper.row));
+    wrapper.rows
+}
+
+pub fn is_newline(grapheme: &str) -> bool {
+    matches!(grapheme, "\n" | "\r\n")
+}
+
+struct Wrapper {
+    width: usize,
+    rows: Vec<Line<'static>>,
+    row: Vec<Span<'static>>,
+    row_width: usize,
+}
+
+impl Wrapper {
+    fn push_word(&mut self, word: &mut Vec<(&str, Style)>) {
+        let word_width: usize = word
+            .iter()
+            .filter(|(grapheme, _)| *grapheme != " ")
+            .map(|(grapheme, _)| grapheme.width())
+            .sum();
+        if self.row_width + word_width > self.width {
+            self.break_row();
+        }
+        for (grapheme, style) in word.drain(..) {
+            let grapheme_width = grapheme.width();
+            if self.row_width + grapheme_width > self.width {
+                // Trailing spaces are allowed to hang off the end of the row
+                if grapheme == " " {
+                    continue;
+                }
+                self.break_row();
+            }
+            match self.row.last_mut() {
+                Some(span) if span.style == style => span.content.to_mut().push_str(grapheme),
+                _ => self.row.push(Span::styled(grapheme.to_owned(), style)),
+            }
+            self.row_width += grapheme_width;
+        }
+    }
+
+    fn break_row(&mut self) {
+        if self.row_width > 0 {
+            self.rows.push(Line::from(std::mem::take(&mut self.row)));
+            self.row_width = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(rows: &[Line]) -> Vec<String> {
+        rows.iter().map(|row| row.to_string()).collect()
+    }
+
+    fn render(
+        view: MessageView<impl Fn(usize) -> Line<'static>>,
+        state: &mut MessageViewState,
+    ) -> Vec<String> {
+        let area = Rect::new(0, 0, 10, 3);
+        let mut buf = Buffer::empty(area);
+        view.render(area, &mut buf, state);
+        (0..area.height)
+            .map(|y| {
+                (0..area.width)
+                    .map(|x| buf[(x, y)].symbol())
+                    .collect::<String>()
+                    .trim_end()
+                    .to_owned()
+            })
+            .collect()
+    }
+
+    fn numbered(len: usize) -> MessageView<impl Fn(usize) -> Line<'static>> {
+        MessageView::new(len, |i| Line::from(format!("m{i}")))
+    }
+
+    #[test]
+    fn wraps_on_words() {
+        let rows = wrap_line(&Line::from("hello there world"), 11);
+        assert_eq!(plain(&rows), ["hello there", "world"]);
+        let rows = wrap_line(&Line::from("hello there world"), 8);
+        assert_eq!(plain(&rows), ["hello ", "there ", "world"]);
+    }
+
+    #[test]
+    fn breaks_long_words_and_keeps_styles() {
+        let line = Line::from(vec![
+            Span::styled("ab", Style::new().bold()),
+            Span::raw("cdefg"),
+        ]);
+        let rows = wrap_line(&line, 3);
+        assert_eq!(plain(&rows), ["abc", "def", "g"]);
+        assert_eq!(rows[0].spans[0], Span::styled("ab", Style::new().bold()));
+    }
+
+    #[test]
+    fn wide_graphemes() {
+        let rows = wrap_line(&Line::from("árvíz😀😀"), 6);
+        assert_eq!(plain(&rows), ["árvíz", "😀😀"]);
+        assert_eq!(plain(&wrap_line(&Line::from(""), 5)), [""]);
+    }
+
+    #[test]
+    fn newlines_start_a_row() {
+        let line = Line::from(vec![Span::raw("a: "), Span::raw("one\n\ntwo three\r\n")]);
+        assert_eq!(
+            plain(&wrap_line(&line, 8)),
+            ["a: one", "", "two ", "three", ""]
+        );
+    }
+
+    #[test]
+    fn follows_the_end() {
+        let mut state = MessageViewState::default();
+        assert_eq!(render(numbered(5), &mut state), ["m2", "m3", "m4"]);
+        assert_eq!(render(numbered(2), &mut state), ["m0", "m1", ""]);
+    }
+
+    #[test]
+    fn scrolling_and_new_message_marker() {
+        let mut state = MessageViewState::default();
+        render(numbered(10), &mut state);
+        state.scroll_up(2);
+        assert_eq!(render(numbered(10), &mut state), ["m5", "m6", "m7"]);
+
+        state.message_added();
+        assert_eq!(render(numbered(11), &mut state), ["m5", "m6", " ↓ 1 new"]);
+
+        state.page_up();
+        state.page_up();
+        assert_eq!(render(numbered(11), &mut state), ["m0", "m1", " ↓ 1 new"]);
+
+        state.scroll_down(5);
+        assert_eq!(render(numbered(11), &mut state), ["m5", "m6", " ↓ 1 new"]);
+
+        state.page_down();
+        state.page_down();
+        assert_eq!(render(numbered(11), &mut state), ["m8", "m9", "m10"]);
+        assert!(state.top.is_none());
+    }
+
+    #[test]
+    fn home_and_end() {
+        let mut state = MessageViewState::default();
+        state.scroll_to_top();
+        assert_eq!(render(numbered(10), &mut state), ["m0", "m1", "m2"]);
+        state.scroll_to_bottom();
+        assert_eq!(render(numbered(10), &mut state), ["m7", "m8", "m9"]);
+        state.show_message(4);
+        assert_eq!(render(numbered(10), &mut state), ["m4", "m5", "m6"]);
+        // Too close to the end to be at the top
+        state.show_message(9);
+        assert_eq!(render(numbered(10), &mut state), ["m7", "m8", "m9"]);
+    }
+
+    #[test]
+    fn scrolls_through_wrapped_rows() {
+        let view = || MessageView::new(3, |i| Line::from(format!("m{i} aaaa bbbb")));
+        let mut state = MessageViewState::default();
+        assert_eq!(render(view(), &mut state), ["bbbb", "m2 aaaa", "bbbb"]);
+        state.scroll_up(1);
+        assert_eq!(render(view(), &mut state), ["m1 aaaa", "bbbb", "m2 aaaa"]);
+    }
+
+    #[test]
+    fn only_visible_messages_are_laid_out() {
+        let laid_out = std::cell::Cell::new(0);
+        let view = MessageView::new(100_000, |i| {
+            laid_out.set(laid_out.get() + 1);
+            Line::from(format!("m{i}"))
+        });
+        render_counted(view);
+        assert!(laid_out.get() < 10);
+    }
+
+    fn render_counted<'a>(view: MessageView<impl Fn(usize) -> Line<'a>>) {
+        let area = Rect::new(0, 0, 10, 3);
+        let mut buf = Buffer::empty(area);
+        view.render(area, &mut buf, &mut MessageViewState::default());
+    }
+
+    #[test]
+    fn widening_keeps_the_scroll_position_valid() {
+        let view = || MessageView::new(3, |i| Line::from(format!("m{i} aaaa bbbb cccc")));
+        let mut state = MessageViewState::default();
+        let render_at = |width, state: &mut MessageViewState| {
+            let area = Rect::new(0, 0, width, 3);
+            view().render(area, &mut Buffer::empty(area), state);
+        };
+        render_at(5, &mut state);
+        state.scroll_up(2);
+        render_at(5, &mut state);
+        assert_eq!(state.top, Some((1, 3)));
+        // m1 is a single row now, the stored row is past its end
+        state.scroll_down(1);
+        render_at(40, &mut state);
+        assert_eq!(state.top, None);
+
+        state.scroll_up(2);
+        render_at(5, &mut state);
+        state.scroll_up(1);
+        render_at(40, &mut state);
+        assert_eq!(state.top, Some((0, 0)));
+    }
+}
@@ -0,0 +1,5 @@
+pub mod message_view;
+pub mod search_overlay;
+pub mod user_list;
+pub mod text_field;
+pub mod timestamp;
@@ -0,0 +1,159 @@
+use super::text_field::TextField;
+use crate::config::Theme;
+use crate::networking::Recipient;
+use crate::search::{Hit, Unloaded};
+use chrono::{Local, TimeZone};
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, List, ListState, Paragraph};
+use ratatui::Frame;
+
+/// The search popup, a query field above the hits
+pub struct SearchOverlay {
+    pub input: TextField,
+    /// The hits of the current query, or why it couldn't be run
+    results: Result<Vec<Hit>, String>,
+    list: ListState,
+    /// The messages only in the chat log, read when the popup opened, or why they couldn't be
+    unloaded: Result<Vec<Unloaded>, String>,
+}
+
+impl SearchOverlay {
+    pub fn new(query: &str, unloaded: Result<Vec<Unloaded>, String>) -> Self {
+        let mut input = TextField::default();
+        input.set_value(query);
+        Self {
+            input,
+            results: Ok(vec![]),
+            list: ListState::default(),
+            unloaded,
+        }
+    }
+
+    pub fn unloaded(&self) -> Result<&[Unloaded], String> {
+        self.unloaded.as_deref().map_err(Clone::clone)
+    }
+
+    pub fn set_results(&mut self, results: Result<Vec<Hit>, String>) {
+        let found = matches!(&results, Ok(hits) if !hits.is_empty());
+        self.list.select(found.then_some(0));
+        self.results = results;
+    }
+
+    pub fn selected(&self) -> Option<&Hit> {
+        let hits = self.results.as_ref().ok()?;
+        hits.get(self.list.selected()?)
+    }
+
+    pub fn select_next(&mut self) {
+        if let Ok(hits) = &self.results {
+            let last = hits.len().saturating_sub(1);
+            let next = self.list.selected().map_or(0, |i| (i + 1).min(last));
+            self.list.select((!hits.is_empty()).then_some(next));
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.list.select_previous();
+    }
+
+    /// Draws the popup over the middle of `area` and puts the cursor in the query field
+    pub fn render(&mut self, frame: &mut Frame, area: Rect, theme: &Theme) {
+        let [area] = Layout::horizontal([Constraint::Percentage(80)])
+            .flex(Flex::Center)
+            .areas(area);
+        let [area] = Layout::vertical([Constraint::Percentage(70)])
+            .flex(Flex::Center)
+            .areas(area);
+        frame.render_widget(Clear, area);
+
+        let status = match &self.results {
+            Ok(hits) => Line::styled(format!("{} hits", hits.len()), theme.timestamp),
+            Err(e) => Line::styled(e.as_str(), Color::Red),
+        };
+        let block = Block::bordered()
+            .title("Search")
+            .title(status.right_aligned())
+            .title_bottom("Enter: jump, Esc: close, from: in: after: before: /regex/")
+            .style(Style::new().fg(theme.accent));
+        let [input_area, hits_area] =
+            Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(block.inner(area));
+        frame.render_widget(block, area);
+
+        frame.render_widget(self.input.widget(input_area), input_area);
+        frame.set_cursor_position(self.input.cursor_position(input_area));
+
+        let hits = self.results.as_deref().unwrap_or_default();
+        let list = List::new(hits.iter().map(|hit| hit_line(hit, theme)))
+            .block(Block::new().borders(Borders::TOP))
+            .highlight_style(Style::new().bg(Color::DarkGray));
+        frame.render_stateful_widget(list, hits_area, &mut self.list);
+        if hits.is_empty() && self.results.is_ok() && !self.input.value().trim().is_empty() {
+            let [_, empty_area] =
+                Layout::vertical([Constraint::Length(1), Constraint::Length(1)]).areas(hits_area);
+            frame.render_widget(Paragraph::new("No matches").italic(), empty_area);
+        }
+    }
+}
+
+/// `2024-05-31 12:00 [bela] cili: text` with the matches highlighted
+fn hit_line<'a>(hit: &'a Hit, theme: &Theme) -> Line<'a> {
+    let time = Local
+        .timestamp_opt(hit.message.time as i64, 0)
+        .earliest()
+        .map(|time| time.format("%Y-%m-%d %H:%M ").to_string())
+        .unwrap_or_default();
+    let conversation = match &hit.conversation {
+        Recipient::Id(name) => name.as_str(),
+        Recipient::All | Recipient::This => "everyone",
+    };
+    let mut spans = vec![
+        Span::styled(time, theme.timestamp),
+        Span::styled(format!("[{conversation}] "), theme.direct),
+        Span::styled(format!("{}: ", hit.message.sender), Style::new().bold()),
+    ];
+    // Newlines would break the line, they are shown as spaces
+    let text = &hit.message.message;
+    let mut end = 0;
+    for range in &hit.matches {
+        spans.push(Span::raw(text[end..range.start].replace('\n', " ")));
+        spans.push(Span::styled(
+            text[range.clone()].replace('\n', " "),
+            Style::new().fg(Color::Black).bg(Color::Yellow),
+        ));
+        end = range.end;
+    }
+    spans.push(Span::raw(text[end..].replace('\n', " ")));
+    Line::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::MessageInformation;
+    use crate::search::Location;
+
+    #[test]
+    fn matches_are_highlighted() {
+        let hit = Hit {
+            conversation: Recipient::Id("bela".into()),
+            location: Location::History(0),
+            message: MessageInformation {
+                sender: "bela".into(),
+                recipient: Recipient::This,
+                message: "lunch?\nLunch!".into(),
+                time: 0,
+            },
+            matches: vec![0..5, 7..12],
+        };
+        let line = hit_line(&hit, &Theme::default());
+        let texts: Vec<_> = line.spans[3..]
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        assert_eq!(texts, ["", "lunch", "? ", "Lunch", "!"]);
+        assert_eq!(line.spans[4].style.bg, Some(Color::Yellow));
+        assert_eq!(line.spans[1].content, "[bela] ");
+    }
+}
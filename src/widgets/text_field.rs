@@ -0,0 +1,369 @@
+use crossterm::event::Event;
+use ratatui::layout::Rect;
+use ratatui::text::Line;
+use ratatui::widgets::Paragraph;
+use tui_input::backend::crossterm::EventHandler;
+use tui_input::{Input, InputRequest};
+
+use super::message_view::is_newline;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A grapheme cluster placed in a text field
+struct Cell<'a> {
+    grapheme: &'a str,
+    /// Index of the first char of the grapheme, [`Input`] counts its cursor in chars
+    char_index: usize,
+    row: usize,
+    column: usize,
+}
+
+/// Lays out `text` in rows of `width` columns.
+///
+/// Words move to the next row as a whole if they fit on it, longer ones are broken between
+/// grapheme clusters, newlines always end the row. Also returns where a cursor after the last
+/// grapheme goes.
+fn layout(text: &str, width: usize) -> (Vec<Cell<'_>>, (usize, usize)) {
+    let width = width.max(1);
+    let graphemes: Vec<&str> = text.graphemes(true).collect();
+    let mut cells = vec![];
+    let (mut row, mut column, mut char_index) = (0, 0, 0);
+    for (i, &grapheme) in graphemes.iter().enumerate() {
+        let starts_word = grapheme != " " && (i == 0 || matches!(graphemes[i - 1], " "));
+        let needed = if starts_word {
+            graphemes[i..]
+                .iter()
+                .take_while(|grapheme| **grapheme != " " && !is_newline(grapheme))
+                .map(|grapheme| grapheme.width())
+                .sum()
+        } else {
+            grapheme.width()
+        };
+        // A newline takes no room, but it still can't go past the end of a full row
+        if column > 0 && (column >= width || (column + needed > width && !is_newline(grapheme))) {
+            row += 1;
+            column = 0;
+        }
+        cells.push(Cell {
+            grapheme,
+            char_index,
+            row,
+            column,
+        });
+        char_index += grapheme.chars().count();
+        if is_newline(grapheme) {
+            row += 1;
+            column = 0;
+        } else {
+            column += grapheme.width();
+        }
+    }
+    if column >= width {
+        row += 1;
+        column = 0;
+    }
+    (cells, (row, column))
+}
+
+/// Row and column of the char index `cursor`, a cursor inside a grapheme cluster is shown at
+/// the start of the cluster
+fn position(cells: &[Cell], end: (usize, usize), cursor: usize) -> (usize, usize) {
+    cells
+        .iter()
+        .find(|cell| {
+            (cell.char_index..cell.char_index + cell.grapheme.chars().count()).contains(&cursor)
+        })
+        .map_or(end, |cell| (cell.row, cell.column))
+}
+
+/// The rows of a text field that are on screen
+pub struct InputWindow {
+    /// First visible row
+    pub top: usize,
+    /// Width of the last render, moving between rows depends on it
+    pub width: usize,
+}
+
+impl InputWindow {
+    #[inline]
+    pub fn empty() -> Self {
+        Self { top: 0, width: 0 }
+    }
+
+    /// Row and column of the char index `cursor`, counted from the start of the text
+    pub fn cursor_position(text: &str, cursor: usize, width: usize) -> (usize, usize) {
+        let (cells, end) = layout(text, width);
+        position(&cells, end, cursor)
+    }
+
+    /// Scrolls to keep the cursor visible and returns the visible rows
+    pub fn visible_rows(
+        &mut self,
+        text: &str,
+        cursor: usize,
+        width: usize,
+        height: usize,
+    ) -> Vec<String> {
+        let height = height.max(1);
+        self.width = width;
+        let (cells, end) = layout(text, width);
+        let (cursor_row, _) = position(&cells, end, cursor);
+        self.top = self.top.min(cursor_row);
+        if cursor_row >= self.top + height {
+            self.top = cursor_row + 1 - height;
+        }
+        let mut rows = vec![String::new(); height];
+        for cell in cells {
+            if (self.top..self.top + height).contains(&cell.row) && !is_newline(cell.grapheme) {
+                rows[cell.row - self.top].push_str(cell.grapheme);
+            }
+        }
+        rows
+    }
+}
+
+/// An [`Input`] together with the part of it that fits on screen
+pub struct TextField {
+    pub input: Input,
+    pub window: InputWindow,
+}
+
+impl Default for TextField {
+    fn default() -> Self {
+        Self {
+            input: "".into(),
+            window: InputWindow::empty(),
+        }
+    }
+}
+
+impl TextField {
+    pub fn value(&self) -> &str {
+        self.input.value()
+    }
+
+    pub fn set_value(&mut self, value: &str) {
+        self.input = value.into();
+        self.window = InputWindow::empty();
+    }
+
+    pub fn reset(&mut self) {
+        self.set_value("");
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        self.input.handle_event(event);
+    }
+
+    pub fn insert_newline(&mut self) {
+        self.input.handle(InputRequest::InsertChar('\n'));
+    }
+
+    pub fn move_up(&mut self) {
+        self.move_rows(-1);
+    }
+
+    pub fn move_down(&mut self) {
+        self.move_rows(1);
+    }
+
+    /// Moves the cursor to another row as laid out in the last render, keeping its column
+    /// where the row is long enough
+    fn move_rows(&mut self, by: isize) {
+        let text = self.input.value();
+        let (cells, end) = layout(text, self.window.width);
+        let (row, column) = position(&cells, end, self.input.cursor());
+        let Some(target) = row.checked_add_signed(by) else {
+            return;
+        };
+        // Every place the cursor can stop at in the target row
+        let mut stops: Vec<_> = cells
+            .iter()
+            .filter(|cell| cell.row == target)
+            .map(|cell| (cell.column, cell.char_index))
+            .collect();
+        if end.0 == target {
+            stops.push((end.1, text.chars().count()));
+        }
+        let stop = stops.iter().rev().find(|(stop, _)| *stop <= column);
+        if let Some((_, cursor)) = stop.or(stops.first()) {
+            self.input.handle(InputRequest::SetCursor(*cursor));
+        }
+    }
+
+    /// Scrolls the window for `rect` and returns the visible part of the text
+    pub fn widget(&mut self, rect: Rect) -> Paragraph<'static> {
+        let rows = self.window.visible_rows(
+            self.input.value(),
+            self.input.cursor(),
+            rect.width as usize,
+            rect.height as usize,
+        );
+        Paragraph::new(rows.into_iter().map(Line::from).collect::<Vec<_>>())
+    }
+
+    /// Where the terminal cursor goes, only valid after [`widget`](Self::widget) was called with
+    /// the same `rect`
+    pub fn cursor_position(&self, rect: Rect) -> (u16, u16) {
+        let (row, column) = InputWindow::cursor_position(
+            self.input.value(),
+            self.input.cursor(),
+            rect.width as usize,
+        );
+        let row = row.saturating_sub(self.window.top);
+        (rect.x + column as u16, rect.y + row as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use ratatui::buffer::Buffer;
+    use ratatui::widgets::Widget;
+
+    fn field(text: &str, cursor: usize) -> TextField {
+        TextField {
+            input: Input::new(text.into()).with_cursor(cursor),
+            window: InputWindow::empty(),
+        }
+    }
+
+    fn render(field: &mut TextField, rect: Rect) -> (Vec<String>, (u16, u16)) {
+        let mut buf = Buffer::empty(rect);
+        field.widget(rect).render(rect, &mut buf);
+        let rows = (rect.top()..rect.bottom())
+            .map(|y| {
+                (rect.left()..rect.right())
+                    .map(|x| buf[(x, y)].symbol())
+                    .collect::<String>()
+                    .trim_end()
+                    .to_owned()
+            })
+            .collect();
+        (rows, field.cursor_position(rect))
+    }
+
+    #[test]
+    fn accented_letters_are_one_column() {
+        let mut field = field("árvíztűrő", 9);
+        assert_eq!(
+            render(&mut field, Rect::new(0, 0, 5, 1)),
+            (vec!["tűrő".to_owned()], (4, 0))
+        );
+        field.input = field.input.with_cursor(1);
+        assert_eq!(
+            render(&mut field, Rect::new(2, 3, 5, 1)),
+            (vec!["árvíz".to_owned()], (3, 3))
+        );
+    }
+
+    #[test]
+    fn wide_graphemes_wrap_as_a_whole() {
+        let mut field = field("a😀😀b", 3);
+        // The cell covered by the second half of a wide grapheme reads as a space
+        assert_eq!(
+            render(&mut field, Rect::new(0, 0, 4, 2)),
+            (vec!["a😀".to_owned(), "😀 b".to_owned()], (2, 1))
+        );
+        assert_eq!(InputWindow::cursor_position("😀😀", 2, 4), (1, 0));
+    }
+
+    #[test]
+    fn cursor_inside_a_cluster_sits_at_its_start() {
+        // "e" followed by a combining acute accent
+        let text = "ae\u{301}b";
+        assert_eq!(InputWindow::cursor_position(text, 2, 10), (0, 1));
+        assert_eq!(InputWindow::cursor_position(text, 3, 10), (0, 2));
+        assert_eq!(InputWindow::cursor_position("e\u{301}", 1, 10), (0, 0));
+        assert_eq!(InputWindow::cursor_position("e\u{301}", 2, 10), (0, 1));
+    }
+
+    #[test]
+    fn newlines_and_words_wrap() {
+        let mut field = field("hi\nhello world", 14);
+        assert_eq!(
+            render(&mut field, Rect::new(0, 0, 8, 3)),
+            (
+                vec!["hi".to_owned(), "hello".to_owned(), "world".to_owned()],
+                (5, 2)
+            )
+        );
+        assert_eq!(InputWindow::cursor_position("hi\n", 3, 8), (1, 0));
+        // A newline after a full row goes on the next one, like the cursor would
+        assert_eq!(InputWindow::cursor_position("aaa\nb", 3, 3), (1, 0));
+        assert_eq!(InputWindow::cursor_position("aaa\nb", 4, 3), (2, 0));
+    }
+
+    #[test]
+    fn moving_between_rows_keeps_the_column() {
+        let mut field = field("hi\nhello world", 14);
+        render(&mut field, Rect::new(0, 0, 8, 3));
+        field.move_up();
+        assert_eq!(field.input.cursor(), 8);
+        field.move_up();
+        // Row "hi" is shorter, the cursor goes to its end
+        assert_eq!(field.input.cursor(), 2);
+        field.move_up();
+        assert_eq!(field.input.cursor(), 2);
+        field.move_down();
+        field.move_down();
+        assert_eq!(field.input.cursor(), 11);
+        field.move_down();
+        assert_eq!(field.input.cursor(), 11);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Edit {
+        Request(InputRequest),
+        Up,
+        Down,
+    }
+
+    fn edit() -> impl Strategy<Value = Edit> {
+        let text = prop::sample::select(vec![
+            'a', ' ', '\n', 'á', 'ő', 'ű', '😀', '漢', '\u{301}', '\u{200d}',
+        ]);
+        let request = prop_oneof![
+            4 => text.prop_map(InputRequest::InsertChar),
+            1 => any::<char>().prop_map(InputRequest::InsertChar),
+            1 => Just(InputRequest::GoToPrevChar),
+            1 => Just(InputRequest::GoToNextChar),
+            1 => Just(InputRequest::GoToPrevWord),
+            1 => Just(InputRequest::GoToNextWord),
+            1 => Just(InputRequest::GoToStart),
+            1 => Just(InputRequest::GoToEnd),
+            1 => Just(InputRequest::DeletePrevChar),
+            1 => Just(InputRequest::DeleteNextChar),
+            1 => Just(InputRequest::DeletePrevWord),
+        ];
+        prop_oneof![
+            8 => request.prop_map(Edit::Request),
+            1 => Just(Edit::Up),
+            1 => Just(Edit::Down),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn random_edits_keep_the_cursor_in_the_rect(
+            edits in prop::collection::vec(edit(), 0..60),
+            width in 1u16..12,
+            height in 1u16..4,
+        ) {
+            let rect = Rect::new(3, 2, width, height);
+            let mut field = TextField::default();
+            for edit in edits {
+                match edit {
+                    Edit::Request(request) => {
+                        field.input.handle(request);
+                    }
+                    Edit::Up => field.move_up(),
+                    Edit::Down => field.move_down(),
+                }
+                let (_, (x, y)) = render(&mut field, rect);
+                prop_assert!(rect.contains((x, y).into()));
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use std::str::FromStr;
+
+/// How the times of messages are shown in the history
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum TimestampFormat {
+    /// `12:34`
+    #[default]
+    Minutes,
+    /// `12:34:56`
+    Seconds,
+    /// `5m ago`
+    Relative,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hh:mm" => Ok(TimestampFormat::Minutes),
+            "hh:mm:ss" => Ok(TimestampFormat::Seconds),
+            "relative" => Ok(TimestampFormat::Relative),
+            _ => Err(format!(
+                "Unknown timestamp format {s}, use hh:mm, hh:mm:ss or relative"
+            )),
+        }
+    }
+}
+
+impl TimestampFormat {
+    /// `time` and `now` are in seconds since the Unix epoch
+    pub fn format(self, time: u64, now: u64) -> String {
+        match self {
+            TimestampFormat::Minutes => local_time(time).format("%H:%M").to_string(),
+            TimestampFormat::Seconds => local_time(time).format("%H:%M:%S").to_string(),
+            TimestampFormat::Relative => {
+                let ago = now.saturating_sub(time);
+                match ago {
+                    0..60 => "now".to_owned(),
+                    60..3600 => format!("{}m ago", ago / 60),
+                    3600..86400 => format!("{}h ago", ago / 3600),
+                    _ => format!("{}d ago", ago / 86400),
+                }
+            }
+        }
+    }
+}
+
+fn local_time(time: u64) -> DateTime<Local> {
+    Local
+        .timestamp_opt(time as i64, 0)
+        .earliest()
+        .unwrap_or_default()
+}
+
+pub fn local_date(time: u64) -> NaiveDate {
+    local_time(time).date_naive()
+}
+
+/// The row put in the history before the first message of a day
+pub fn day_separator(date: NaiveDate) -> String {
+    format!("── {} ──", date.format("%A, %Y-%m-%d"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let noon = Local
+            .with_ymd_and_hms(2024, 5, 31, 12, 34, 56)
+            .unwrap()
+            .timestamp() as u64;
+        assert_eq!(TimestampFormat::Minutes.format(noon, noon), "12:34");
+        assert_eq!(TimestampFormat::Seconds.format(noon, noon), "12:34:56");
+        let relative = |ago| TimestampFormat::Relative.format(noon, noon + ago);
+        assert_eq!(relative(59), "now");
+        assert_eq!(relative(5 * 60), "5m ago");
+        assert_eq!(relative(3 * 3600 + 59), "3h ago");
+        assert_eq!(relative(2 * 86400), "2d ago");
+        // A clock behind the message isn't in the future
+        assert_eq!(TimestampFormat::Relative.format(noon, 0), "now");
+
+        assert_eq!(day_separator(local_date(noon)), "── Friday, 2024-05-31 ──");
+        assert_eq!("HH:MM:SS".parse(), Ok(TimestampFormat::Seconds));
+        assert!("iso".parse::<TimestampFormat>().is_err());
+    }
+}
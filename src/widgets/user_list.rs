@@ -0,0 +1,162 @@
+use crate::networking::Recipient;
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListState};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UserEntry<'a> {
+    /// Always the first entry, sends to everyone on the server
+    Everyone,
+    User(&'a str),
+}
+
+impl UserEntry<'_> {
+    pub fn recipient(&self) -> Recipient {
+        match self {
+            UserEntry::Everyone => Recipient::All,
+            UserEntry::User(name) => Recipient::Id(name.to_string()),
+        }
+    }
+}
+
+/// Selection and the typed filter of the users pane
+#[derive(Default)]
+pub struct UserListState {
+    list: ListState,
+    filter: String,
+}
+
+impl UserListState {
+    /// The everyone entry followed by the users matching the filter, ignoring case
+    pub fn entries<'a>(&self, users: &'a [String]) -> Vec<UserEntry<'a>> {
+        let filter = self.filter.to_lowercase();
+        std::iter::once(UserEntry::Everyone)
+            .chain(
+                users
+                    .iter()
+                    .filter(|user| user.to_lowercase().contains(&filter))
+                    .map(|user| UserEntry::User(user)),
+            )
+            .collect()
+    }
+
+    pub fn selected<'a>(&self, users: &'a [String]) -> Option<UserEntry<'a>> {
+        let entries = self.entries(users);
+        let index = self.list.selected()?.min(entries.len() - 1);
+        Some(entries[index])
+    }
+
+    pub fn select_next(&mut self, users: &[String]) {
+        let last = self.entries(users).len() - 1;
+        let next = self.list.selected().map_or(0, |i| (i + 1).min(last));
+        self.list.select(Some(next));
+    }
+
+    pub fn select_previous(&mut self, users: &[String]) {
+        let last = self.entries(users).len() - 1;
+        let previous = self
+            .list
+            .selected()
+            .map_or(0, |i| i.min(last).saturating_sub(1));
+        self.list.select(Some(previous));
+    }
+
+    pub fn push_filter(&mut self, c: char) {
+        self.filter.push(c);
+        self.list.select(Some(0));
+    }
+
+    pub fn pop_filter(&mut self) {
+        self.filter.pop();
+        self.list.select(Some(0));
+    }
+
+    pub fn clear_filter(&mut self) {
+        self.filter.clear();
+    }
+
+    /// Builds the list widget, `block` gets the user count and the filter as its title
+    pub fn widget<'a>(
+        &mut self,
+        users: &'a [String],
+        block: Block<'a>,
+        highlight: Color,
+    ) -> List<'a> {
+        let entries = self.entries(users);
+        if let Some(selected) = self.list.selected() {
+            self.list.select(Some(selected.min(entries.len() - 1)));
+        }
+        let mut block = block.title(format!("Users ({})", users.len()));
+        if !self.filter.is_empty() {
+            block = block.title_bottom(format!("filter: {}", self.filter));
+        }
+        List::new(entries.into_iter().map(|entry| match entry {
+            UserEntry::Everyone => Line::styled("everyone", Style::new().italic()),
+            UserEntry::User(name) => Line::raw(name),
+        }))
+        .block(block)
+        .highlight_style(Style::new().fg(Color::Black).bg(highlight))
+    }
+
+    pub fn list_state(&mut self) -> &mut ListState {
+        &mut self.list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users() -> Vec<String> {
+        vec!["Kiss Anna".into(), "bela".into(), "Annamari".into()]
+    }
+
+    #[test]
+    fn filter_ignores_case_and_keeps_everyone() {
+        let users = users();
+        let mut state = UserListState::default();
+        for c in "ANN".chars() {
+            state.push_filter(c);
+        }
+        assert_eq!(
+            state.entries(&users),
+            [
+                UserEntry::Everyone,
+                UserEntry::User("Kiss Anna"),
+                UserEntry::User("Annamari")
+            ]
+        );
+        for _ in 0..3 {
+            state.pop_filter();
+        }
+        state.push_filter('e');
+        assert_eq!(
+            state.entries(&users),
+            [UserEntry::Everyone, UserEntry::User("bela")]
+        );
+    }
+
+    #[test]
+    fn navigation_stays_in_bounds() {
+        let users = users();
+        let mut state = UserListState::default();
+        assert_eq!(state.selected(&users), None);
+        state.select_previous(&users);
+        assert_eq!(state.selected(&users), Some(UserEntry::Everyone));
+        for _ in 0..10 {
+            state.select_next(&users);
+        }
+        assert_eq!(state.selected(&users), Some(UserEntry::User("Annamari")));
+        state.select_previous(&users);
+        assert_eq!(
+            state.selected(&users).unwrap().recipient(),
+            Recipient::Id("bela".into())
+        );
+        // The list shrinking under the selection
+        assert_eq!(
+            state.selected(&users[..1]),
+            Some(UserEntry::User("Kiss Anna"))
+        );
+        assert_eq!(UserEntry::Everyone.recipient(), Recipient::All);
+    }
+}